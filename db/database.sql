@@ -1,12 +1,71 @@
+-- Latest schema of the cache, used by sqlx to check the queries at compile time. Keep it in
+-- sync with the migrations in src/database.rs
 CREATE TABLE IF NOT EXISTS Articles (
-  id TEXT,
-  source TEXT,
+  id TEXT NOT NULL,
+  source TEXT NOT NULL,
   title TEXT NOT NULL,
   sub_title TEXT NOT NULL,
   content TEXT NOT NULL,
   date DATETIME,
+  read BOOLEAN NOT NULL DEFAULT 0,
+  starred BOOLEAN NOT NULL DEFAULT 0,
+  inserted DATETIME NOT NULL DEFAULT 0,
+  link TEXT,
+  authors TEXT NOT NULL DEFAULT '',
+  categories TEXT NOT NULL DEFAULT '',
+  comments TEXT,
+  sub_title_html TEXT NOT NULL DEFAULT '',
+  content_html TEXT NOT NULL DEFAULT '',
+  last_seen INTEGER,
   PRIMARY KEY (id, source)
 );
+CREATE VIRTUAL TABLE IF NOT EXISTS ArticlesSearch USING fts5(
+  id UNINDEXED,
+  source UNINDEXED,
+  title,
+  sub_title,
+  content
+);
+CREATE TABLE IF NOT EXISTS Enclosures (
+  article_id TEXT NOT NULL,
+  source TEXT NOT NULL,
+  position INTEGER NOT NULL,
+  url TEXT NOT NULL,
+  mime_type TEXT,
+  length INTEGER,
+  duration INTEGER,
+  PRIMARY KEY (article_id, source, position)
+);
+CREATE TABLE IF NOT EXISTS Links (
+  article_id TEXT NOT NULL,
+  source TEXT NOT NULL,
+  position INTEGER NOT NULL,
+  url TEXT NOT NULL,
+  text TEXT NOT NULL,
+  PRIMARY KEY (article_id, source, position)
+);
+CREATE TABLE IF NOT EXISTS Sources (
+  url TEXT NOT NULL PRIMARY KEY,
+  etag TEXT,
+  last_modified TEXT,
+  last_success DATETIME,
+  last_failure DATETIME,
+  error_kind TEXT,
+  error_message TEXT,
+  failures INTEGER NOT NULL DEFAULT 0,
+  next_retry DATETIME,
+  ttl INTEGER,
+  skip_hours TEXT NOT NULL DEFAULT '',
+  skip_days TEXT NOT NULL DEFAULT '',
+  update_period INTEGER,
+  next_update DATETIME,
+  title TEXT,
+  description TEXT,
+  site_link TEXT,
+  language TEXT,
+  icon TEXT,
+  generator TEXT
+);
 INSERT
 OR REPLACE INTO Articles (id, source, title, sub_title, content, date)
 VALUES
@@ -1,18 +1,90 @@
+use chrono::Local;
+use crossterm::event::{KeyCode, KeyEvent, KeyModifiers};
+use reqwest::Client;
+use sqlx::SqlitePool;
 use std::{
+    cell::RefCell,
+    collections::{BTreeSet, HashMap, HashSet},
     convert::TryFrom,
     io,
-    sync::{Arc, RwLock},
+    path::PathBuf,
+    rc::Rc,
+    sync::{Arc, Mutex, RwLock},
 };
+use tokio::sync::mpsc;
 use tui::{
     backend::Backend,
-    layout::{Constraint, Direction, Layout},
+    layout::{Constraint, Direction, Layout, Rect},
     style::{Color, Modifier, Style},
     text::{Span, Spans, Text},
-    widgets::{Block, Borders, List, ListItem, ListState, Paragraph, Wrap},
+    widgets::{Block, Borders, Clear, List, ListItem, ListState, Paragraph, Wrap},
     Terminal,
 };
 
-use crate::content::{Article, ArticleMap};
+use crate::{
+    configuration::{self, Config, Source},
+    content::{Article, ArticleMap, FeedMetadata},
+    database::{self, SearchResult},
+    download::{self, Download, DownloadState},
+    render::{self, RenderCache, RenderedArticle},
+    search,
+    update::{self, FetchStatus, Refresh},
+};
+
+/// Entry of the sources pane, filters the articles shown in the list
+#[derive(Clone, Debug, PartialEq, Eq)]
+enum Filter {
+    All,
+    Tag(String),
+    Source(String),
+}
+
+/// Value derived from the articles, computed again only when its key changes
+struct Memo<K, V> {
+    cell: RefCell<Option<(K, V)>>,
+}
+
+impl<K: PartialEq, V: Clone> Memo<K, V> {
+    fn new() -> Self {
+        Memo {
+            cell: RefCell::new(None),
+        }
+    }
+
+    /// Returns the value computed for the key, the closure is called only if the key changed
+    fn get<F: FnOnce() -> V>(&self, key: K, compute: F) -> V {
+        if let Some((cached, value)) = &*self.cell.borrow() {
+            if *cached == key {
+                return value.clone();
+            }
+        }
+        let value = compute();
+        *self.cell.borrow_mut() = Some((key, value.clone()));
+        value
+    }
+}
+
+/// State the articles in the list are filtered by
+type ListKey = (u64, usize, Filter, bool, String);
+
+/// Url given to subscribe with the source of its feed, or the reason it couldn't be added
+type Subscription = (String, anyhow::Result<Source>);
+
+/// Search being written in the prompt
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Prompt {
+    // Filters the articles in the list
+    Filter,
+    // Full-text search of all the articles in the cache
+    Archive,
+}
+
+/// Pane of the main view that receives the movement keys
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Focus {
+    Sources,
+    Articles,
+}
 
 pub struct App<B>
 where
@@ -20,33 +92,131 @@ where
 {
     // Set of the articles
     pub content: Arc<RwLock<ArticleMap>>,
+    // Status of the updates of the sources by url
+    pub status: Arc<RwLock<HashMap<String, FetchStatus>>>,
+    // Urls of the sources being fetched
+    pub fetching: Arc<RwLock<HashSet<String>>>,
+    // Information published by the feeds by url
+    pub feeds: Arc<RwLock<HashMap<String, FeedMetadata>>>,
+    // Channel to refresh the sources, set when the update task is started
+    pub refresh: Option<mpsc::UnboundedSender<Refresh>>,
+    // Error of the last command, shown in the status line until the next key
+    pub error: Option<String>,
+    // Errors of the background tasks, shown in the status line by the next draw
+    pub task_errors: Arc<Mutex<Vec<String>>>,
     // List state
     pub list_state: ListState,
     // TUI terminal
     pub terminal: Terminal<B>,
+    // Database pool to persist the user state of the articles
+    pool: Arc<SqlitePool>,
+    // Sources in the config, in the same order
+    sources: Vec<Arc<Source>>,
+    sources_by_url: HashMap<String, Arc<Source>>,
+    // State of the sources pane
+    sources_state: ListState,
+    focus: Focus,
     article: Option<Arc<Article>>,
     max_scroll: Option<u16>,
     scroll: u16,
+    // Width the article in the view is rendered at
+    article_width: u16,
+    // Html of the article in the view and the preview rendered at their width
+    render_cache: RenderCache,
     view_article: bool,
+    // Show only the starred articles in the list
+    view_starred: bool,
+    // Show the selected article next to the list
+    view_preview: bool,
+    // Text searched in the articles, the list shows only the matching ones
+    query: String,
+    // The keys are written in the search prompt
+    prompt: Option<Prompt>,
+    // Full-text search of the cache
+    archive_query: String,
+    // Results of the full-text search shown instead of the list, set by the search task
+    results: Arc<Mutex<Option<Vec<SearchResult>>>>,
+    results_state: ListState,
+    // Downloads of the enclosures, in the order they were started
+    downloads: Arc<RwLock<Vec<Download>>>,
+    download_dir: PathBuf,
+    client: Client,
+    // Index of the selected enclosure of the article in the view
+    enclosure: usize,
+    // Selected link of the article in the view, when the links are listed
+    link_picker: Option<ListState>,
+    // Where the subscribed sources are added
+    config_path: PathBuf,
+    // Urls checked before being added to the sources
+    subscribing: HashSet<String>,
+    // Results of the subscriptions with their url, set by their tasks
+    subscribed: Arc<Mutex<Vec<Subscription>>>,
+    // Entries of the sources pane with their unread articles, by version of the content and
+    // number of sources
+    filters: Memo<(u64, usize), Vec<(Filter, usize)>>,
+    // Articles shown in the list, by version of the content, number of sources, filter, starred
+    // view and query
+    articles: Memo<ListKey, Rc<Vec<Arc<Article>>>>,
 }
 
 impl<B> App<B>
 where
     B: Backend,
 {
-    pub fn new(terminal: Terminal<B>) -> App<B> {
+    pub fn new(terminal: Terminal<B>, pool: &Arc<SqlitePool>, config: &Config) -> App<B> {
+        let sources = &config.sources;
+        let mut sources_state = ListState::default();
+        sources_state.select(Some(0));
         App::<B> {
             content: Arc::new(RwLock::new(ArticleMap::default())),
+            status: Arc::new(RwLock::new(HashMap::new())),
+            fetching: Arc::new(RwLock::new(HashSet::new())),
+            feeds: Arc::new(RwLock::new(HashMap::new())),
+            refresh: None,
+            error: None,
+            task_errors: Arc::new(Mutex::new(Vec::new())),
             list_state: ListState::default(),
             terminal,
+            pool: Arc::clone(pool),
+            sources: sources.to_vec(),
+            sources_by_url: sources
+                .iter()
+                .map(|source| (source.url.clone(), Arc::clone(source)))
+                .collect(),
+            sources_state,
+            focus: Focus::Articles,
             view_article: false,
             article: None,
             scroll: 0,
             max_scroll: None,
+            article_width: 0,
+            render_cache: RenderCache::default(),
+            view_starred: false,
+            view_preview: false,
+            query: String::new(),
+            prompt: None,
+            archive_query: String::new(),
+            results: Arc::new(Mutex::new(None)),
+            results_state: ListState::default(),
+            downloads: Arc::new(RwLock::new(Vec::new())),
+            download_dir: config.download_dir.clone(),
+            client: Client::new(),
+            enclosure: 0,
+            link_picker: None,
+            config_path: config.config_path.clone(),
+            subscribing: HashSet::new(),
+            subscribed: Arc::new(Mutex::new(Vec::new())),
+            filters: Memo::new(),
+            articles: Memo::new(),
         }
     }
 
     pub fn draw(&mut self) -> io::Result<()> {
+        self.finish_subscriptions();
+        // Only the latest error fits in the status line
+        if let Some(err) = self.task_errors.lock().unwrap().drain(..).next_back() {
+            self.error = Some(err);
+        }
         if self.view_article {
             self.draw_article_view()
         } else {
@@ -55,66 +225,208 @@ where
     }
 
     fn draw_main_view(&mut self) -> io::Result<()> {
-        let content = &self.content;
+        let articles = self.articles();
+        let title = if self.view_starred { "Starred" } else { "List" };
+        // Entries of the sources pane with the number of unread articles and if the last update
+        // of the source failed
+        let filters: Vec<(String, usize, bool)> = {
+            let filters = self.filters();
+            let status = self.status.read().unwrap();
+            filters
+                .iter()
+                .map(|(filter, unread)| {
+                    let failed = match filter {
+                        Filter::Source(url) => {
+                            status.get(url).is_some_and(|status| status.error.is_some())
+                        }
+                        _ => false,
+                    };
+                    (self.filter_label(filter), *unread, failed)
+                })
+                .collect()
+        };
+        let preview = if self.view_preview {
+            self.selected_article()
+        } else {
+            None
+        };
+        // Results of the full-text search with the label of their source
+        let results: Option<Vec<(String, SearchResult)>> = self.search_results().map(|results| {
+            results
+                .into_iter()
+                .map(|result| {
+                    let label = self.filter_label(&Filter::Source(result.source.clone()));
+                    (label, result)
+                })
+                .collect()
+        });
+        if results.as_ref().is_some_and(|results| !results.is_empty())
+            && self.results_state.selected().is_none()
+        {
+            self.results_state.select(Some(0));
+        }
+        let archive_title = format!("Search: {}", self.archive_query);
+        let focus = self.focus;
+        let status = self.status_line();
+        let query = &self.query;
         let list_state = &mut self.list_state;
+        let results_state = &mut self.results_state;
+        let sources_state = &mut self.sources_state;
+        let render_cache = &mut self.render_cache;
         self.terminal.draw(|f| {
+            let (main, status_area) = split_status(f.size());
+            f.render_widget(Paragraph::new(status), status_area);
+
+            let constraints = if preview.is_some() {
+                [
+                    Constraint::Percentage(20),
+                    Constraint::Percentage(40),
+                    Constraint::Percentage(40),
+                ]
+            } else {
+                [
+                    Constraint::Percentage(20),
+                    Constraint::Percentage(80),
+                    Constraint::Percentage(0),
+                ]
+            };
             let chunks = Layout::default()
                 .direction(Direction::Horizontal)
-                .constraints([Constraint::Percentage(100)].as_ref())
-                .split(f.size());
+                .constraints(constraints.as_ref())
+                .split(main);
+
+            let highlight_style = Style::default()
+                .fg(Color::Yellow)
+                .add_modifier(Modifier::BOLD);
+            // Only the focused pane shows the selection highlighted
+            let pane_highlight = |pane: Focus| {
+                if pane == focus {
+                    highlight_style
+                } else {
+                    Style::default().add_modifier(Modifier::BOLD)
+                }
+            };
 
-            let content = content.read().unwrap();
+            let sources: Vec<ListItem> = filters
+                .iter()
+                .map(|(label, unread, failed)| {
+                    if *failed {
+                        let label = format!("{} ({}) !", label, unread);
+                        return ListItem::new(Spans::from(label))
+                            .style(Style::default().fg(Color::Red));
+                    }
+                    let item = ListItem::new(Spans::from(format!("{} ({})", label, unread)));
+                    if *unread == 0 {
+                        item.style(Style::default().add_modifier(Modifier::DIM))
+                    } else {
+                        item
+                    }
+                })
+                .collect();
+            let sources = List::new(sources)
+                .block(Block::default().borders(Borders::ALL).title("Sources"))
+                .highlight_style(pane_highlight(Focus::Sources))
+                .highlight_symbol("> ");
+            f.render_stateful_widget(sources, chunks[0], sources_state);
 
-            let items: Vec<ListItem> = content.articles()
+            let items: Vec<ListItem> = articles
                 .iter()
                 .map(|article| {
-                    let lines = vec![Spans::from(article.title.clone())];
-                    ListItem::new(lines)
+                    let marker = if article.starred { "* " } else { "  " };
+                    let mut line = search::highlight_line(&article.title, query, Style::default());
+                    line.0.insert(0, Span::raw(marker));
+                    let item = ListItem::new(vec![line]);
+                    if article.read {
+                        item.style(Style::default().add_modifier(Modifier::DIM))
+                    } else {
+                        item
+                    }
                 })
                 .collect();
 
             let items = List::new(items)
-                .block(Block::default().borders(Borders::ALL).title("List"))
-                .highlight_style(
-                    Style::default()
-                        .fg(Color::Yellow)
-                        .add_modifier(Modifier::BOLD),
-                )
+                .block(Block::default().borders(Borders::ALL).title(title))
+                .highlight_style(pane_highlight(Focus::Articles))
                 .highlight_symbol("> ");
-            f.render_stateful_widget(items, chunks[0], list_state);
+
+            // The results of the full-text search replace the list
+            if let Some(results) = &results {
+                let items: Vec<ListItem> = results
+                    .iter()
+                    .map(|(label, result)| {
+                        let dim = Style::default().add_modifier(Modifier::DIM);
+                        let mut snippet = search::highlight_snippet(&result.snippet, dim);
+                        snippet
+                            .0
+                            .insert(0, Span::styled(format!("{}: ", label), dim));
+                        ListItem::new(vec![Spans::from(result.title.clone()), snippet])
+                    })
+                    .collect();
+                let items = List::new(items)
+                    .block(Block::default().borders(Borders::ALL).title(format!(
+                        "{} ({})",
+                        archive_title,
+                        results.len()
+                    )))
+                    .highlight_style(pane_highlight(Focus::Articles))
+                    .highlight_symbol("> ");
+                f.render_stateful_widget(items, chunks[1], results_state);
+            } else {
+                f.render_stateful_widget(items, chunks[1], list_state);
+            }
+
+            if let Some(article) = &preview {
+                let rendered = render_cache.get(article, chunks[2].width.saturating_sub(2));
+                let paragraph = Paragraph::new(article_text(article, rendered, query, None))
+                    .block(Block::default().title("Preview").borders(Borders::ALL))
+                    .wrap(Wrap { trim: false });
+                f.render_widget(paragraph, chunks[2]);
+            }
         })
     }
 
     fn draw_article_view(&mut self) -> io::Result<()> {
-        if self.article.is_some() {
+        let status = self.status_line();
+        if let Some(article) = self.article.as_ref() {
             // Get borrow from self since is not possible inside of closure
-            let article = self.article.as_ref().unwrap();
             let scroll = &mut self.scroll;
             let max_scroll = &mut self.max_scroll;
+            let query = &self.query;
+            let enclosure = self.enclosure;
+            let link_picker = &mut self.link_picker;
+            let article_width = &mut self.article_width;
+            let render_cache = &mut self.render_cache;
             self.terminal.draw(|f| {
-                let chunks = Layout::default()
-                    .direction(Direction::Horizontal)
-                    .constraints([Constraint::Percentage(100)].as_ref())
-                    .split(f.size());
-
-                // Multi-line text for the content of an article
-                let mut text = Text::from(Span::styled(
-                    &article.title,
-                    Style::default().add_modifier(Modifier::BOLD),
-                ));
-                text.extend(Text::raw(&article.sub_title));
-                text.extend(Text::raw(&article.content));
+                let (main, status_area) = split_status(f.size());
+                f.render_widget(Paragraph::new(status), status_area);
 
-                // If max_scroll is not set calculate max_scroll or has changed
-                let current_max_scroll = u16::try_from(text.height())
+                // Multi-line text for the content of an article, inside the borders
+                let width = main.width.saturating_sub(2);
+                let rendered = render_cache.get(article, width);
+                let text = article_text(article, rendered, query, Some(enclosure));
+
+                // If max_scroll is not set calculate max_scroll or has changed, the borders
+                // are not part of the visible text
+                let rows: usize = text
+                    .lines
+                    .iter()
+                    .map(|line| render::wrapped_rows(line, width))
+                    .sum();
+                let current_max_scroll = u16::try_from(rows)
                     .unwrap_or(u16::MAX)
-                    .saturating_sub(f.size().height - 4);
+                    .saturating_sub(main.height.saturating_sub(2));
                 if max_scroll.is_none() || max_scroll.unwrap() != current_max_scroll {
+                    // The text is wrapped again when the width changes, the same part of the
+                    // article stays visible
+                    if let Some(previous) = max_scroll.filter(|_| *article_width != width) {
+                        *scroll = rescale_scroll(*scroll, previous, current_max_scroll);
+                    }
                     *max_scroll = Some(current_max_scroll);
                     if *scroll > current_max_scroll {
                         *scroll = current_max_scroll;
                     }
                 }
+                *article_width = width;
 
                 let offset = (*scroll, 0);
                 let paragraph = Paragraph::new(text)
@@ -122,30 +434,380 @@ where
                     .alignment(tui::layout::Alignment::Left)
                     .scroll(offset)
                     .wrap(Wrap { trim: false });
-                f.render_widget(paragraph, chunks[0]);
+                f.render_widget(paragraph, main);
+
+                // Numbered list of the links over the article
+                if let Some(link_picker) = link_picker {
+                    let links: Vec<ListItem> = article
+                        .links
+                        .iter()
+                        .enumerate()
+                        .map(|(index, link)| {
+                            let mut spans = vec![Span::raw(format!("{}. ", index + 1))];
+                            if !link.text.is_empty() {
+                                spans.push(Span::raw(format!("{} ", link.text)));
+                            }
+                            spans.push(Span::styled(
+                                link.url.clone(),
+                                Style::default().add_modifier(Modifier::DIM),
+                            ));
+                            ListItem::new(Spans::from(spans))
+                        })
+                        .collect();
+                    let area = overlay_area(main, links.len() as u16 + 2);
+                    let list = List::new(links)
+                        .block(
+                            Block::default()
+                                .title("Links (o: open, y: yank, a: subscribe)")
+                                .borders(Borders::ALL),
+                        )
+                        .highlight_style(
+                            Style::default()
+                                .fg(Color::Yellow)
+                                .add_modifier(Modifier::BOLD),
+                        )
+                        .highlight_symbol("> ");
+                    f.render_widget(Clear, area);
+                    f.render_stateful_widget(list, area, link_picker);
+                }
             })
         } else {
             self.draw_main_view()
         }
     }
 
+    /// Returns the entries of the sources pane with their number of unread articles: all the
+    /// articles, the tags and then the sources. The sources of the articles that are not in the
+    /// config anymore are shown at the end. They are counted again only when the content or the
+    /// sources change.
+    fn filters(&self) -> Vec<(Filter, usize)> {
+        let content = self.content.read().unwrap();
+        let key = (content.version(), self.sources.len());
+        self.filters.get(key, || {
+            // Unread articles by source, in a single pass over the articles
+            let mut unread: HashMap<&str, usize> = HashMap::new();
+            for article in content.articles() {
+                let count = unread.entry(article.source.as_str()).or_default();
+                if !article.read {
+                    *count += 1;
+                }
+            }
+            let unread_of = |url: &str| unread.get(url).copied().unwrap_or_default();
+            let tags: BTreeSet<&String> = self
+                .sources
+                .iter()
+                .flat_map(|source| source.tags.iter())
+                .collect();
+            let other_sources: BTreeSet<&str> = unread
+                .keys()
+                .filter(|url| !self.sources_by_url.contains_key(**url))
+                .copied()
+                .collect();
+            let mut filters = vec![(Filter::All, unread.values().sum())];
+            filters.extend(tags.into_iter().map(|tag| {
+                let count = self
+                    .sources_by_url
+                    .values()
+                    .filter(|source| has_tag(source, tag))
+                    .map(|source| unread_of(&source.url))
+                    .sum();
+                (Filter::Tag(tag.clone()), count)
+            }));
+            filters.extend(
+                self.sources
+                    .iter()
+                    .map(|source| (Filter::Source(source.url.clone()), unread_of(&source.url))),
+            );
+            filters.extend(
+                other_sources
+                    .into_iter()
+                    .map(|url| (Filter::Source(String::from(url)), unread_of(url))),
+            );
+            filters
+        })
+    }
+
+    fn filter_label(&self, filter: &Filter) -> String {
+        match filter {
+            Filter::All => String::from("All"),
+            Filter::Tag(tag) => format!("#{}", tag),
+            // The name in the config, then the title of the feed
+            Filter::Source(url) => self
+                .sources_by_url
+                .get(url)
+                .and_then(|source| source.name.clone())
+                .or_else(|| {
+                    let feeds = self.feeds.read().unwrap();
+                    feeds.get(url).and_then(|feed| feed.title.clone())
+                })
+                .unwrap_or_else(|| url.clone()),
+        }
+    }
+
+    /// Checks if the article is shown with the filter, a tag matches also its sub folders
+    fn matches(&self, filter: &Filter, article: &Article) -> bool {
+        match filter {
+            Filter::All => true,
+            Filter::Tag(tag) => self
+                .sources_by_url
+                .get(&article.source)
+                .is_some_and(|source| has_tag(source, tag)),
+            Filter::Source(url) => article.source == *url,
+        }
+    }
+
+    /// Returns the filter selected in the sources pane
+    fn selected_filter(&self) -> Filter {
+        self.sources_state
+            .selected()
+            .and_then(|index| self.filters().into_iter().nth(index))
+            .map(|(filter, _)| filter)
+            .unwrap_or(Filter::All)
+    }
+
+    /// Returns the articles shown in the list. They are searched again only when the content,
+    /// the sources, the filter, the starred view or the query change.
+    fn articles(&self) -> Rc<Vec<Arc<Article>>> {
+        let filter = self.selected_filter();
+        let content = self.content.read().unwrap();
+        let key = (
+            content.version(),
+            self.sources.len(),
+            filter.clone(),
+            self.view_starred,
+            self.query.clone(),
+        );
+        self.articles.get(key, || {
+            let articles = content
+                .articles()
+                .iter()
+                .filter(|article| !self.view_starred || article.starred)
+                .filter(|article| self.matches(&filter, article))
+                .filter(|article| self.matches_query(article))
+                .cloned()
+                .collect();
+            Rc::new(articles)
+        })
+    }
+
+    /// Checks if the title, sub title, content or source of the article match the search
+    fn matches_query(&self, article: &Article) -> bool {
+        self.query.is_empty()
+            || search::is_match(&article.title, &self.query)
+            || search::is_match(&article.sub_title, &self.query)
+            || search::is_match(&article.content, &self.query)
+            || search::is_match(
+                &self.filter_label(&Filter::Source(article.source.clone())),
+                &self.query,
+            )
+            || search::is_match(&article.source, &self.query)
+    }
+
+    /// Line at the bottom of the screen with the search prompt
+    fn status_line(&self) -> Spans<'static> {
+        let cursor = Span::styled(" ", Style::default().add_modifier(Modifier::REVERSED));
+        if self.prompt == Some(Prompt::Filter) {
+            Spans::from(vec![Span::raw(format!("/{}", self.query)), cursor])
+        } else if self.prompt == Some(Prompt::Archive) {
+            Spans::from(vec![
+                Span::raw(format!("Search archive: {}", self.archive_query)),
+                cursor,
+            ])
+        } else if let Some(error) = &self.error {
+            Spans::from(Span::styled(error.clone(), Style::default().fg(Color::Red)))
+        } else if !self.query.is_empty() {
+            Spans::from(format!(
+                "Search: {} ({} matches)",
+                self.query,
+                self.articles().len()
+            ))
+        } else {
+            let mut spans = self.update_progress();
+            let downloads = self.download_progress();
+            if !spans.is_empty() && !downloads.is_empty() {
+                spans.push(Span::raw(" | "));
+            }
+            spans.extend(downloads);
+            // The status of the selected source, or the list of the failed ones
+            let status = match (self.view_article, self.selected_filter()) {
+                (false, Filter::Source(url)) => self.source_status_line(&url).0,
+                _ => self.failed_sources(),
+            };
+            if !spans.is_empty() && !status.is_empty() {
+                spans.push(Span::raw(" | "));
+            }
+            spans.extend(status);
+            Spans::from(spans)
+        }
+    }
+
+    /// Number of the sources being fetched and the urls being subscribed
+    fn update_progress(&self) -> Vec<Span<'static>> {
+        let mut progress = Vec::new();
+        let fetching = self.fetching.read().unwrap().len();
+        if fetching > 0 {
+            let total = self.sources.iter().filter(|source| source.enabled).count();
+            progress.push(format!("Updating {} of {} sources", fetching, total));
+        }
+        if let Some(url) = self.subscribing.iter().next() {
+            progress.push(format!("Subscribing to {}", url));
+        }
+        if progress.is_empty() {
+            return Vec::new();
+        }
+        vec![Span::styled(
+            progress.join(" | "),
+            Style::default().fg(Color::Yellow),
+        )]
+    }
+
+    /// Progress of the running downloads, or the result of the last one if it just finished
+    fn download_progress(&self) -> Vec<Span<'static>> {
+        let downloads = self.downloads.read().unwrap();
+        let running: Vec<&Download> = downloads
+            .iter()
+            .filter(|download| download.state == DownloadState::Running)
+            .collect();
+        if let Some(download) = running.first() {
+            let mut progress = format!(
+                "Downloading {} {}",
+                download.file_name(),
+                download::format_size(download.received)
+            );
+            if let Some(total) = download.total.filter(|total| *total > 0) {
+                progress.push_str(&format!(
+                    " of {} ({}%)",
+                    download::format_size(total),
+                    download.received.min(total) * 100 / total
+                ));
+            }
+            if running.len() > 1 {
+                progress.push_str(&format!(" and {} more", running.len() - 1));
+            }
+            return vec![Span::styled(progress, Style::default().fg(Color::Yellow))];
+        }
+        let last = downloads.last().filter(|download| {
+            download
+                .finished
+                .is_some_and(|finished| finished.elapsed() < download::SHOW_FINISHED)
+        });
+        match last.map(|download| &download.state) {
+            Some(DownloadState::Done(path)) => vec![Span::styled(
+                format!("Downloaded {}", path.display()),
+                Style::default().fg(Color::Green),
+            )],
+            Some(DownloadState::Failed(error)) => vec![Span::styled(
+                format!("Download failed: {}", error),
+                Style::default().fg(Color::Red),
+            )],
+            _ => Vec::new(),
+        }
+    }
+
+    /// Names of the sources that failed their last update
+    fn failed_sources(&self) -> Vec<Span<'static>> {
+        let status = self.status.read().unwrap();
+        let failed: Vec<String> = self
+            .sources
+            .iter()
+            .filter(|source| {
+                source.enabled
+                    && status
+                        .get(&source.url)
+                        .is_some_and(|status| status.error.is_some())
+            })
+            .map(|source| self.filter_label(&Filter::Source(source.url.clone())))
+            .collect();
+        if failed.is_empty() {
+            return Vec::new();
+        }
+        vec![Span::styled(
+            format!("Failed: {}", failed.join(", ")),
+            Style::default().fg(Color::Red),
+        )]
+    }
+
+    /// Status of the updates of the selected source
+    fn source_status_line(&self, url: &str) -> Spans<'static> {
+        let status = self.status.read().unwrap();
+        let status = match status.get(url) {
+            Some(status) => status,
+            None => return Spans::from("Not updated yet"),
+        };
+        let last_success = match status.last_success {
+            Some(time) => format!(
+                "Updated {}",
+                time.with_timezone(&Local).format("%Y-%m-%d %H:%M")
+            ),
+            None => String::from("Never updated"),
+        };
+        match &status.error {
+            Some(error) => {
+                let retry = status
+                    .next_retry
+                    .map(|time| {
+                        let time = time.with_timezone(&Local).format("%H:%M:%S");
+                        format!(", retry at {}", time)
+                    })
+                    .unwrap_or_default();
+                Spans::from(vec![
+                    Span::styled(
+                        format!("{} (failures: {}{})", error.kind, status.failures, retry),
+                        Style::default().fg(Color::Red),
+                    ),
+                    Span::raw(format!(" | {} | {}", last_success, error.message)),
+                ])
+            }
+            None => Spans::from(last_success),
+        }
+    }
+
+    /// Returns the results of the full-text search, if they are shown
+    fn search_results(&self) -> Option<Vec<SearchResult>> {
+        self.results.lock().unwrap().clone()
+    }
+
+    /// Article in the view, or the selected one if the list has the focus
+    fn current_article(&self) -> Option<Arc<Article>> {
+        if self.view_article {
+            self.article.clone()
+        } else if self.focus == Focus::Articles {
+            self.selected_article()
+        } else {
+            None
+        }
+    }
+
+    /// Returns the article currently selected in the list or in the search results
+    fn selected_article(&self) -> Option<Arc<Article>> {
+        match self.search_results() {
+            Some(results) => {
+                let result = results.get(self.results_state.selected()?)?;
+                let key = (result.id.clone(), result.source.clone());
+                self.content.read().unwrap().get(&key)
+            }
+            None => {
+                let index = self.list_state.selected()?;
+                self.articles().get(index).cloned()
+            }
+        }
+    }
+
     pub fn set_view_article(&mut self, view: bool) {
-        if (view) && (view != self.view_article) && (self.list_state.selected().is_some()) {
-            self.view_article = true;
+        if view && (view != self.view_article) && (self.focus == Focus::Articles) {
             // Get the article if is selected
-            let index = self.list_state.selected().unwrap();
-            {
-                let content = self.content.read().unwrap();
-                let mut articles = content.articles().iter();
-                for _ in 0..index {
-                    articles.next();
-                }
-                self.article = Some(Arc::clone(articles.next().unwrap()));
+            self.article = self.selected_article();
+            if let Some(article) = &self.article {
+                self.view_article = true;
+                let key = (article.id.clone(), article.source.clone());
+                self.set_read(vec![key], true);
             }
         } else {
             self.article = None;
             self.max_scroll = None;
             self.scroll = 0;
+            self.enclosure = 0;
+            self.link_picker = None;
             self.view_article = false;
         }
     }
@@ -156,44 +818,614 @@ where
                 .scroll
                 .saturating_add(1)
                 .min(self.max_scroll.unwrap_or(0));
+        } else if self.focus == Focus::Sources {
+            let len = self.filters().len();
+            select_next(&mut self.sources_state, len);
+            self.list_state.select(None);
         } else {
-            let content = self.content.read().unwrap();
-            // Select an article if there is one to select
-            if !content.articles().is_empty() {
-                let i = match self.list_state.selected() {
-                    Some(i) => {
-                        if i >= content.articles().len() - 1 {
-                            0
-                        } else {
-                            i + 1
-                        }
-                    }
-                    None => 0,
-                };
-                self.list_state.select(Some(i));
-            }
+            self.select_next_article();
         }
     }
 
     pub fn up_key_event(&mut self) {
         if self.view_article {
             self.scroll = self.scroll.saturating_sub(1);
+        } else if self.focus == Focus::Sources {
+            let len = self.filters().len();
+            select_previous(&mut self.sources_state, len);
+            self.list_state.select(None);
         } else {
-            let content = self.content.read().unwrap();
-            // Select an article if there is one to select
-            if !content.articles().is_empty() {
-                let i = match self.list_state.selected() {
-                    Some(i) => {
-                        if i == 0 {
-                            content.articles().len() - 1
-                        } else {
-                            i - 1
-                        }
+            self.select_previous_article();
+        }
+    }
+
+    /// Closes the article or moves the focus to the sources pane
+    pub fn left_key_event(&mut self) {
+        if self.view_article {
+            self.set_view_article(false);
+        } else {
+            self.focus = Focus::Sources;
+        }
+    }
+
+    /// Moves the focus to the list or opens the selected article
+    pub fn right_key_event(&mut self) {
+        if self.focus == Focus::Sources {
+            self.focus = Focus::Articles;
+        } else {
+            self.set_view_article(true);
+        }
+    }
+
+    /// Moves the focus between the sources pane and the list
+    pub fn switch_focus_event(&mut self) {
+        if !self.view_article {
+            self.focus = match self.focus {
+                Focus::Sources => Focus::Articles,
+                Focus::Articles => Focus::Sources,
+            };
+        }
+    }
+
+    /// Shows or hides the preview of the selected article
+    pub fn toggle_preview_event(&mut self) {
+        self.view_preview = !self.view_preview;
+    }
+
+    /// The terminal was resized, the articles are rendered again at the new width by the next draw
+    pub fn resize_event(&mut self) {
+        self.render_cache.clear();
+    }
+
+    /// Selects the next entry of the list or of the search results
+    fn select_next_article(&mut self) {
+        match self.search_results() {
+            Some(results) => select_next(&mut self.results_state, results.len()),
+            None => {
+                let len = self.articles().len();
+                select_next(&mut self.list_state, len);
+            }
+        }
+    }
+
+    /// Selects the previous entry of the list or of the search results
+    fn select_previous_article(&mut self) {
+        match self.search_results() {
+            Some(results) => select_previous(&mut self.results_state, results.len()),
+            None => {
+                let len = self.articles().len();
+                select_previous(&mut self.list_state, len);
+            }
+        }
+    }
+
+    /// Closes the article or the results of the full-text search
+    pub fn escape_event(&mut self) {
+        if self.view_article {
+            self.set_view_article(false);
+        } else {
+            *self.results.lock().unwrap() = None;
+            self.results_state.select(None);
+        }
+    }
+
+    /// Opens the search prompt
+    pub fn search_event(&mut self) {
+        self.prompt = Some(Prompt::Filter);
+    }
+
+    /// Opens the prompt of the full-text search of the cache
+    pub fn archive_search_event(&mut self) {
+        if !self.view_article {
+            self.prompt = Some(Prompt::Archive);
+            self.archive_query.clear();
+        }
+    }
+
+    /// Checks if the keys are written in the search prompt
+    pub fn is_prompting(&self) -> bool {
+        self.prompt.is_some()
+    }
+
+    /// Edits the search, the list is filtered while the query is written
+    pub fn prompt_key_event(&mut self, event: KeyEvent) {
+        if self.prompt == Some(Prompt::Archive) {
+            self.archive_prompt_key_event(event);
+            return;
+        }
+        match event.code {
+            KeyCode::Char(c) if is_text(event) => self.query.push(c),
+            KeyCode::Backspace => {
+                if self.query.pop().is_none() {
+                    self.prompt = None;
+                }
+            }
+            KeyCode::Enter => self.prompt = None,
+            KeyCode::Esc => {
+                self.prompt = None;
+                self.query.clear();
+            }
+            _ => return,
+        }
+        if !self.view_article {
+            let selected = if self.articles().is_empty() {
+                None
+            } else {
+                Some(0)
+            };
+            self.list_state.select(selected);
+        }
+    }
+
+    /// Edits the full-text search, the query is run when confirmed
+    fn archive_prompt_key_event(&mut self, event: KeyEvent) {
+        match event.code {
+            KeyCode::Char(c) if is_text(event) => self.archive_query.push(c),
+            KeyCode::Backspace if self.archive_query.is_empty() => self.prompt = None,
+            KeyCode::Backspace => {
+                self.archive_query.pop();
+            }
+            KeyCode::Enter => {
+                self.prompt = None;
+                self.focus = Focus::Articles;
+                self.results_state.select(None);
+                let pool = Arc::clone(&self.pool);
+                let results = Arc::clone(&self.results);
+                let query = self.archive_query.clone();
+                let task_errors = Arc::clone(&self.task_errors);
+                tokio::spawn(async move {
+                    match database::search(&pool, &query, search::SEARCH_LIMIT).await {
+                        Ok(found) => *results.lock().unwrap() = Some(found),
+                        Err(err) => task_errors
+                            .lock()
+                            .unwrap()
+                            .push(format!("Failed to search: {}", err)),
+                    }
+                });
+            }
+            KeyCode::Esc => self.prompt = None,
+            _ => {}
+        }
+    }
+
+    /// Jumps to the next or previous match, the lines of the article or the articles in the list
+    pub fn next_match_event(&mut self, forward: bool) {
+        if self.query.is_empty() {
+            return;
+        }
+        if let Some(article) = self.article.as_ref() {
+            let width = self.article_width;
+            let rendered = self.render_cache.get(article, width);
+            // The scroll is in rows of the wrapped text, the rows of the matching lines are
+            // counted at the width of the view
+            let mut row = 0;
+            let mut rows: Vec<usize> = Vec::new();
+            for line in article_text(article, rendered, "", Some(self.enclosure)).lines {
+                let text: String = line.0.iter().map(|span| span.content.as_ref()).collect();
+                if search::is_match(&text, &self.query) {
+                    rows.push(row);
+                }
+                row += render::wrapped_rows(&line, width);
+            }
+            let scroll = usize::from(self.scroll);
+            let row = if forward {
+                rows.iter()
+                    .find(|row| **row > scroll)
+                    .or_else(|| rows.first())
+            } else {
+                rows.iter()
+                    .rev()
+                    .find(|row| **row < scroll)
+                    .or_else(|| rows.last())
+            };
+            if let Some(row) = row {
+                self.scroll = u16::try_from(*row)
+                    .unwrap_or(u16::MAX)
+                    .min(self.max_scroll.unwrap_or(u16::MAX));
+            }
+        } else if forward {
+            // The list shows only the matching articles
+            self.select_next_article();
+        } else {
+            self.select_previous_article();
+        }
+    }
+
+    /// Toggles the read flag of the selected article
+    pub fn toggle_read_event(&mut self) {
+        if !self.view_article && self.focus == Focus::Articles {
+            if let Some(article) = self.selected_article() {
+                let key = (article.id.clone(), article.source.clone());
+                self.set_read(vec![key], !article.read);
+            }
+        }
+    }
+
+    /// Marks every article in the list as read
+    pub fn mark_all_read_event(&mut self) {
+        if !self.view_article {
+            let keys = self
+                .articles()
+                .iter()
+                .filter(|article| !article.read)
+                .map(|article| (article.id.clone(), article.source.clone()))
+                .collect();
+            self.set_read(keys, true);
+        }
+    }
+
+    /// Toggles the starred flag of the selected article
+    pub fn toggle_starred_event(&mut self) {
+        let article = if self.view_article {
+            self.article.clone()
+        } else {
+            self.selected_article()
+        };
+        if let Some(article) = article {
+            let key = (article.id.clone(), article.source.clone());
+            let starred = !article.starred;
+            let changed = self.content.write().unwrap().set_starred(&key, starred);
+            if let Some(changed) = changed {
+                if self.view_article {
+                    self.article = Some(changed);
+                }
+                let pool = Arc::clone(&self.pool);
+                let task_errors = Arc::clone(&self.task_errors);
+                tokio::spawn(async move {
+                    if let Err(err) = database::set_starred(&pool, &key, starred).await {
+                        task_errors
+                            .lock()
+                            .unwrap()
+                            .push(format!("Failed to save the starred article: {}", err));
                     }
-                    None => 0,
-                };
-                self.list_state.select(Some(i));
+                });
             }
         }
     }
+
+    /// Refreshes the source of the selected article, or the sources of the selected filter
+    pub fn refresh_event(&mut self) {
+        let refresh = match (self.current_article(), self.selected_filter()) {
+            (Some(article), _) => vec![Refresh::Source(article.source.clone())],
+            (None, Filter::All) => vec![Refresh::All],
+            (None, Filter::Tag(tag)) => self
+                .sources
+                .iter()
+                .filter(|source| has_tag(source, &tag))
+                .map(|source| Refresh::Source(source.url.clone()))
+                .collect(),
+            (None, Filter::Source(url)) => vec![Refresh::Source(url)],
+        };
+        self.send_refresh(refresh);
+    }
+
+    /// Link of the article in the view or of the selected one in the list
+    pub fn link(&self) -> Option<String> {
+        self.current_article()
+            .and_then(|article| article.link.clone())
+    }
+
+    /// Lists the links of the article in the view to pick one
+    pub fn link_picker_event(&mut self) {
+        let has_links = self
+            .article
+            .as_ref()
+            .is_some_and(|article| !article.links.is_empty());
+        if has_links {
+            let mut state = ListState::default();
+            state.select(Some(0));
+            self.link_picker = Some(state);
+        }
+    }
+
+    pub fn is_picking_link(&self) -> bool {
+        self.link_picker.is_some()
+    }
+
+    /// Closes the list of the links and returns the selected one
+    pub fn pick_link(&mut self) -> Option<String> {
+        let index = self.link_picker.take()?.selected()?;
+        let article = self.article.as_ref()?;
+        article.links.get(index).map(|link| link.url.clone())
+    }
+
+    /// Moves in the list of the links, a number selects the link with it
+    pub fn link_picker_key_event(&mut self, event: KeyEvent) {
+        let len = self
+            .article
+            .as_ref()
+            .map_or(0, |article| article.links.len());
+        let state = match &mut self.link_picker {
+            Some(state) => state,
+            None => return,
+        };
+        match event.code {
+            KeyCode::Char('j') | KeyCode::Down => select_next(state, len),
+            KeyCode::Char('k') | KeyCode::Up => select_previous(state, len),
+            KeyCode::Char(c) if c.is_ascii_digit() => {
+                let number = c.to_digit(10).unwrap() as usize;
+                if number > 0 && number <= len {
+                    state.select(Some(number - 1));
+                }
+            }
+            KeyCode::Char('a') => {
+                if let Some(url) = self.pick_link() {
+                    self.subscribe(url);
+                }
+            }
+            KeyCode::Esc | KeyCode::Char('q') => self.link_picker = None,
+            _ => {}
+        }
+    }
+
+    /// Checks in a task that the url is a feed, or finds the feed the page links, then adds it to
+    /// the sources in the config file
+    fn subscribe(&mut self, url: String) {
+        if self.sources_by_url.contains_key(&url) || self.subscribing.contains(&url) {
+            self.error = Some(format!("Already subscribed to {}", url));
+            return;
+        }
+        self.subscribing.insert(url.clone());
+        let client = self.client.clone();
+        let config_path = self.config_path.clone();
+        let subscribed = Arc::clone(&self.subscribed);
+        tokio::spawn(async move {
+            let result = async {
+                let source = Source::new(update::find_feed(&client, &url).await?);
+                if configuration::add_sources(&config_path, vec![source.clone()])? == 0 {
+                    anyhow::bail!("already subscribed to {}", source.url);
+                }
+                Ok(source)
+            }
+            .await;
+            subscribed.lock().unwrap().push((url, result));
+        });
+    }
+
+    /// Fetches the sources added by the finished subscriptions, or shows why they failed
+    fn finish_subscriptions(&mut self) {
+        let subscribed: Vec<Subscription> = self.subscribed.lock().unwrap().drain(..).collect();
+        for (url, result) in subscribed {
+            self.subscribing.remove(&url);
+            match result {
+                Ok(source) => {
+                    let source = Arc::new(source);
+                    self.sources.push(Arc::clone(&source));
+                    self.sources_by_url
+                        .insert(source.url.clone(), Arc::clone(&source));
+                    self.send_refresh(vec![Refresh::Subscribe(source)]);
+                }
+                Err(err) => self.error = Some(format!("Failed to subscribe: {}", err)),
+            }
+        }
+    }
+
+    /// Selects the next enclosure of the article in the view
+    pub fn next_enclosure_event(&mut self) {
+        if let Some(article) = &self.article {
+            if !article.enclosures.is_empty() {
+                self.enclosure = (self.enclosure + 1) % article.enclosures.len();
+            }
+        }
+    }
+
+    /// Downloads the selected enclosure of the article in the view
+    pub fn download_event(&mut self) {
+        let enclosure = self
+            .article
+            .as_ref()
+            .and_then(|article| article.enclosures.get(self.enclosure));
+        if let Some(enclosure) = enclosure {
+            download::download(
+                &self.client,
+                &enclosure.url,
+                &self.download_dir,
+                &self.downloads,
+            );
+        }
+    }
+
+    /// Refreshes all the sources
+    pub fn refresh_all_event(&mut self) {
+        self.send_refresh(vec![Refresh::All]);
+    }
+
+    fn send_refresh(&self, refresh: Vec<Refresh>) {
+        if let Some(sender) = &self.refresh {
+            for refresh in refresh {
+                // The update task runs as long as the application
+                let _ = sender.send(refresh);
+            }
+        }
+    }
+
+    /// Switches the list between all the articles and only the starred ones
+    pub fn toggle_starred_view_event(&mut self) {
+        if !self.view_article {
+            self.view_starred = !self.view_starred;
+            self.list_state.select(None);
+        }
+    }
+
+    /// Sets the read flag in memory and persists the changed articles in the database
+    fn set_read(&mut self, keys: Vec<(String, String)>, read: bool) {
+        let changed: Vec<(String, String)> = {
+            let mut content = self.content.write().unwrap();
+            keys.into_iter()
+                .filter(|key| content.set_read(key, read).is_some())
+                .collect()
+        };
+        if !changed.is_empty() {
+            let pool = Arc::clone(&self.pool);
+            let task_errors = Arc::clone(&self.task_errors);
+            tokio::spawn(async move {
+                if let Err(err) = database::set_read(&pool, &changed, read).await {
+                    task_errors
+                        .lock()
+                        .unwrap()
+                        .push(format!("Failed to save the read articles: {}", err));
+                }
+            });
+        }
+    }
+}
+
+/// Multi-line text for the content of an article with the matches of the query highlighted, the
+/// rendered html is shown instead of the plain text when there is one
+fn article_text<'a>(
+    article: &'a Article,
+    rendered: &RenderedArticle,
+    query: &str,
+    enclosure: Option<usize>,
+) -> Text<'a> {
+    let mut text = search::highlight_text(
+        &article.title,
+        query,
+        Style::default().add_modifier(Modifier::BOLD),
+    );
+    text.extend(article_header(article, enclosure));
+    text.extend(html_text(&rendered.sub_title, &article.sub_title, query));
+    text.extend(html_text(&rendered.content, &article.content, query));
+    text
+}
+
+/// Styled lines of the rendered html, the plain text is used when there is no html or it can't
+/// be rendered
+fn html_text<'a>(
+    rendered: &Option<Vec<Spans<'static>>>,
+    plain: &'a str,
+    query: &str,
+) -> Vec<Spans<'a>> {
+    match rendered {
+        Some(lines) => lines
+            .iter()
+            .map(|line| search::highlight_spans(line.clone(), query))
+            .collect(),
+        None => search::highlight_text(plain, query, Style::default()).lines,
+    }
+}
+
+/// Checks if the key writes its character, the shortcuts with control or alt don't
+fn is_text(event: KeyEvent) -> bool {
+    !event
+        .modifiers
+        .intersects(KeyModifiers::CONTROL | KeyModifiers::ALT)
+}
+
+/// Scroll keeping the same position relative to the end of the text, after it was wrapped again
+/// at another width
+fn rescale_scroll(scroll: u16, max_scroll: u16, new_max_scroll: u16) -> u16 {
+    if max_scroll == 0 {
+        return 0;
+    }
+    let scroll = u32::from(scroll) * u32::from(new_max_scroll) / u32::from(max_scroll);
+    u16::try_from(scroll).unwrap_or(new_max_scroll)
+}
+
+/// Lines under the title with the authors, link, categories, comments and enclosures of the
+/// article, the selected enclosure is highlighted when there are several
+fn article_header(article: &Article, enclosure: Option<usize>) -> Vec<Spans<'_>> {
+    let dim = Style::default().add_modifier(Modifier::DIM);
+    let mut lines = Vec::new();
+    if !article.authors.is_empty() {
+        lines.push(format!("By {}", article.authors.join(", ")));
+    }
+    if let Some(link) = &article.link {
+        lines.push(format!("Link: {}", link));
+    }
+    if !article.categories.is_empty() {
+        lines.push(format!("Categories: {}", article.categories.join(", ")));
+    }
+    if let Some(comments) = &article.comments {
+        lines.push(format!("Comments: {}", comments));
+    }
+    let mut lines: Vec<Spans> = lines
+        .into_iter()
+        .map(|line| Spans::from(Span::styled(line, dim)))
+        .collect();
+    for (index, enclosure_item) in article.enclosures.iter().enumerate() {
+        let details: Vec<String> = enclosure_item
+            .mime_type
+            .clone()
+            .into_iter()
+            .chain(enclosure_item.length.map(download::format_size))
+            .chain(enclosure_item.duration.map(download::format_duration))
+            .collect();
+        let mut line = format!("Enclosure: {}", enclosure_item.url);
+        if !details.is_empty() {
+            line.push_str(&format!(" ({})", details.join(", ")));
+        }
+        let style = if article.enclosures.len() > 1 && enclosure == Some(index) {
+            dim.add_modifier(Modifier::REVERSED)
+        } else {
+            dim
+        };
+        lines.push(Spans::from(Span::styled(line, style)));
+    }
+    lines
+}
+
+/// Checks if the source has the tag or one of its sub folders
+fn has_tag(source: &Source, tag: &str) -> bool {
+    source.tags.iter().any(|source_tag| {
+        source_tag == tag
+            || (source_tag.starts_with(tag) && source_tag[tag.len()..].starts_with('/'))
+    })
+}
+
+/// Area in the middle of the screen for a list with the height, at most the whole area
+fn overlay_area(area: Rect, height: u16) -> Rect {
+    let width = area.width.saturating_sub(4).min(100);
+    let height = height.min(area.height.saturating_sub(2));
+    Rect::new(
+        area.x + (area.width - width) / 2,
+        area.y + (area.height - height) / 2,
+        width,
+        height,
+    )
+}
+
+/// Splits the area leaving the last line for the status
+fn split_status(area: Rect) -> (Rect, Rect) {
+    let chunks = Layout::default()
+        .direction(Direction::Vertical)
+        .constraints([Constraint::Min(0), Constraint::Length(1)].as_ref())
+        .split(area);
+    (chunks[0], chunks[1])
+}
+
+/// Selects the next element of a list, going back to the first after the last one
+fn select_next(state: &mut ListState, len: usize) {
+    // Select an element if there is one to select
+    if len > 0 {
+        let i = match state.selected() {
+            Some(i) => {
+                if i >= len - 1 {
+                    0
+                } else {
+                    i + 1
+                }
+            }
+            None => 0,
+        };
+        state.select(Some(i));
+    }
+}
+
+/// Selects the previous element of a list, going to the last before the first one
+fn select_previous(state: &mut ListState, len: usize) {
+    // Select an element if there is one to select
+    if len > 0 {
+        let i = match state.selected() {
+            Some(i) => {
+                if i == 0 {
+                    len - 1
+                } else {
+                    i - 1
+                }
+            }
+            None => 0,
+        };
+        state.select(Some(i));
+    }
 }
@@ -1,21 +1,108 @@
-use clap::{crate_description, crate_name, crate_version, value_t, App, Arg};
-use serde::Deserialize;
+use chrono::{DateTime, Duration, FixedOffset, Utc};
+use clap::{crate_description, crate_name, crate_version, value_t, App, Arg, SubCommand};
+use serde::{Deserialize, Serialize};
 use std::{
-    collections::HashSet,
+    collections::{BTreeMap, HashSet},
     env,
     ffi::OsString,
-    fs::{create_dir_all, File},
+    fs::{self, create_dir_all, File},
     io::{self, BufReader, Read},
     path::{Path, PathBuf},
     sync::Arc,
 };
 
 // Configuration structure for serialization and deserialization
-#[derive(Deserialize, Debug, Eq, PartialEq)]
+#[derive(Serialize, Deserialize, Debug, Eq, PartialEq, Default)]
 struct ConfigFile {
     update_interval: Option<u64>,
     // NOTE: This could be a Url and we could check the validity of our config
     sources: Option<Vec<String>>,
+    retention: Option<Retention>,
+    // Sources with more information, written as an array of tables `[[feeds]]`
+    feeds: Option<Vec<Source>>,
+    // Directory where the enclosures are downloaded, a leading `~` is the home directory
+    download_dir: Option<String>,
+    // Command opening the links, the url replaces `{}` or `%s` or is added at the end
+    opener: Option<String>,
+}
+
+/// A source with the information given by the user
+#[derive(Serialize, Deserialize, Debug, Eq, PartialEq, Clone)]
+pub struct Source {
+    pub url: String,
+    // Name to show instead of the url
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
+    // Categories of the source, a nested folder is written as `folder/sub_folder`
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub tags: Vec<String>,
+    // Overrides the global update interval for this source
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub update_interval: Option<u64>,
+    // Disabled sources are kept in the config but never updated
+    #[serde(default = "default_enabled", skip_serializing_if = "is_enabled")]
+    pub enabled: bool,
+    // Additional headers sent when requesting the source
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub headers: BTreeMap<String, String>,
+}
+
+fn default_enabled() -> bool {
+    true
+}
+
+fn is_enabled(enabled: &bool) -> bool {
+    *enabled
+}
+
+impl Source {
+    pub fn new(url: String) -> Source {
+        Source {
+            url,
+            name: None,
+            tags: Vec::new(),
+            update_interval: None,
+            enabled: true,
+            headers: BTreeMap::new(),
+        }
+    }
+}
+
+/// Commands that are executed instead of starting the interface
+#[derive(Debug, Eq, PartialEq, Clone)]
+pub enum Command {
+    // Add the sources of an OPML file to the config file
+    ImportOpml(PathBuf),
+    // Print the sources as OPML
+    ExportOpml,
+    // Print the articles in the cache matching the query
+    Search(String),
+}
+
+/// How long the articles are kept in the cache. The starred articles are always kept.
+///
+/// In the config file it's written as `retention = "forever"`, `retention = { days = 30 }` or
+/// `retention = { per_source = 100 }`.
+#[derive(Serialize, Deserialize, Debug, Eq, PartialEq, Clone, Copy, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum Retention {
+    #[default]
+    Forever,
+    // Keep the articles published in the last N days
+    Days(u64),
+    // Keep the newest N articles of each source
+    PerSource(u64),
+}
+
+impl Retention {
+    /// Checks if an article published at the date is already expired, articles without a date
+    /// never are since they will expire based on the time they were fetched
+    pub fn is_expired(&self, date: Option<DateTime<FixedOffset>>) -> bool {
+        match (self, date) {
+            (Retention::Days(days), Some(date)) => date < Utc::now() - Duration::days(*days as i64),
+            _ => false,
+        }
+    }
 }
 
 // Configuration structure for use in the application
@@ -26,7 +113,14 @@ pub struct Config {
     pub cache_path: PathBuf,
     // Defaults to 5 minutes
     pub update_interval: u64,
-    pub sources: HashSet<Arc<String>>,
+    pub sources: Vec<Arc<Source>>,
+    pub retention: Retention,
+    // Defaults to `~/Downloads`
+    pub download_dir: PathBuf,
+    // Program and arguments of the command opening the links, defaults to `$BROWSER` or
+    // `xdg-open`
+    pub opener: Vec<String>,
+    pub command: Option<Command>,
 }
 
 /**
@@ -64,16 +158,83 @@ fn create_config_path(path_arg: Option<&str>) -> io::Result<PathBuf> {
     Ok(path)
 }
 
-fn read_config_file(path: &Path) -> io::Result<ConfigFile> {
+fn read_config_contents(path: &Path) -> io::Result<String> {
     let file = File::open(path)?;
     let mut buf_reader = BufReader::new(file);
     let mut contents = String::new();
     buf_reader.read_to_string(&mut contents)?;
-    match toml::from_str(&contents) {
+    Ok(contents)
+}
+
+fn parse_config_file(contents: &str) -> io::Result<ConfigFile> {
+    match toml::from_str(contents) {
         Ok(config_file) => Ok(config_file),
         // Convert error from serde to io to pass it down
-        Err(err) => Err(io::Error::new(io::ErrorKind::Other, err)),
+        Err(err) => Err(io::Error::other(err)),
+    }
+}
+
+fn read_config_file(path: &Path) -> io::Result<ConfigFile> {
+    parse_config_file(&read_config_contents(path)?)
+}
+
+/// Appends the sources to the config file as `[[feeds]]` tables. The file is not serialized
+/// again, so the comments and the layout written by the user are kept.
+fn append_feeds(path: &Path, contents: &str, sources: &[Source]) -> io::Result<()> {
+    #[derive(Serialize)]
+    struct Feeds<'a> {
+        feeds: &'a [Source],
+    }
+    let tables = toml::to_string(&Feeds { feeds: sources }).map_err(io::Error::other)?;
+    let mut contents = String::from(contents);
+    if !contents.is_empty() {
+        if !contents.ends_with('\n') {
+            contents.push('\n');
+        }
+        contents.push('\n');
     }
+    contents.push_str(&tables);
+    // The tables can't be appended if the feeds are written as an inline array
+    parse_config_file(&contents)?;
+    fs::write(path, contents)
+}
+
+/// Returns all the sources in the config file, both the legacy list of urls and the feeds
+fn config_file_sources(config_file: &ConfigFile) -> Vec<Source> {
+    let mut urls = HashSet::new();
+    config_file
+        .sources
+        .iter()
+        .flatten()
+        .map(|url| Source::new(url.clone()))
+        .chain(config_file.feeds.iter().flatten().cloned())
+        .filter(|source| urls.insert(source.url.clone()))
+        .collect()
+}
+
+/// Reads all the sources in the config file
+pub fn read_sources(path: &Path) -> io::Result<Vec<Source>> {
+    Ok(config_file_sources(&read_config_file(path)?))
+}
+
+/// Adds the sources to the feeds of the config file, skipping the ones with an url already present.
+/// Returns the number of sources added.
+pub fn add_sources(path: &Path, sources: Vec<Source>) -> io::Result<usize> {
+    let contents = read_config_contents(path)?;
+    let config_file = parse_config_file(&contents)?;
+    let mut urls: HashSet<String> = config_file_sources(&config_file)
+        .into_iter()
+        .map(|source| source.url)
+        .collect();
+    let new_sources: Vec<Source> = sources
+        .into_iter()
+        .filter(|source| urls.insert(source.url.clone()))
+        .collect();
+    let count = new_sources.len();
+    if count > 0 {
+        append_feeds(path, &contents, &new_sources)?;
+    }
+    Ok(count)
 }
 
 fn create_cache_path() -> io::Result<PathBuf> {
@@ -89,6 +250,95 @@ fn create_cache_path() -> io::Result<PathBuf> {
     Ok(path)
 }
 
+/// Path of the download directory, replacing a leading `~` with the home directory
+fn download_dir(dir: Option<&str>) -> PathBuf {
+    let dir = dir.unwrap_or("~/Downloads");
+    match dir.strip_prefix('~') {
+        Some(rest) if rest.is_empty() || rest.starts_with('/') => {
+            PathBuf::from(format!("{}{}", env::var("HOME").unwrap(), rest))
+        }
+        _ => PathBuf::from(dir),
+    }
+}
+
+/// Program and arguments of the opener, the first command of `$BROWSER` or `xdg-open` are used
+/// when it's not configured
+fn opener(opener: Option<&str>, browser: Option<String>) -> Vec<String> {
+    opener
+        .map(String::from)
+        .or_else(|| {
+            // $BROWSER can be a list of commands separated by colons
+            browser.and_then(|browser| browser.split(':').next().map(String::from))
+        })
+        .map(|command| split_command(&command))
+        .filter(|command| !command.is_empty())
+        .unwrap_or_else(|| vec![String::from("xdg-open")])
+}
+
+/// Splits a command in words like a shell: the quotes and the backslashes keep the spaces in a
+/// word, there are no variables
+fn split_command(command: &str) -> Vec<String> {
+    let mut words = Vec::new();
+    // The word being read, an empty quoted word is still a word
+    let mut word: Option<String> = None;
+    let mut chars = command.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '\'' => {
+                let word = word.get_or_insert_with(String::new);
+                word.extend(chars.by_ref().take_while(|c| *c != '\''));
+            }
+            '"' => {
+                let word = word.get_or_insert_with(String::new);
+                while let Some(c) = chars.next() {
+                    match c {
+                        '"' => break,
+                        // Only these characters are escaped inside double quotes
+                        '\\' => match chars.next() {
+                            Some(c @ ('"' | '\\' | '$' | '`')) => word.push(c),
+                            Some(c) => {
+                                word.push('\\');
+                                word.push(c);
+                            }
+                            None => word.push('\\'),
+                        },
+                        c => word.push(c),
+                    }
+                }
+            }
+            '\\' => {
+                if let Some(c) = chars.next() {
+                    word.get_or_insert_with(String::new).push(c);
+                }
+            }
+            c if c.is_whitespace() => words.extend(word.take()),
+            c => word.get_or_insert_with(String::new).push(c),
+        }
+    }
+    words.extend(word);
+    words
+}
+
+/// Arguments of the opener for the url, it replaces the `{}` or `%s` placeholders or is added at
+/// the end
+pub fn opener_args(opener: &[String], url: &str) -> Vec<String> {
+    if opener
+        .iter()
+        .any(|arg| arg.contains("{}") || arg.contains("%s"))
+    {
+        opener
+            .iter()
+            .map(|arg| arg.replace("{}", url).replace("%s", url))
+            .collect()
+    } else {
+        opener
+            .iter()
+            .cloned()
+            .chain(std::iter::once(String::from(url)))
+            .collect()
+    }
+}
+
 // Return a configuration instance
 pub fn config<I, T>(args: I) -> io::Result<Config>
 where
@@ -114,14 +364,49 @@ where
                 .help("Interval of time in seconds to check the sources for updates")
                 .takes_value(true),
         )
+        .subcommand(
+            SubCommand::with_name("import-opml")
+                .about("Adds the sources of an OPML file to the config file")
+                .arg(
+                    Arg::with_name("FILE")
+                        .help("Path to the OPML file")
+                        .required(true),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("export-opml")
+                .about("Prints the sources in the config file as OPML"),
+        )
+        .subcommand(
+            SubCommand::with_name("search")
+                .about("Searches the articles in the cache")
+                .arg(
+                    Arg::with_name("QUERY")
+                        .help("Words to search in the title, sub title and content")
+                        .required(true)
+                        .multiple(true),
+                ),
+        )
         .get_matches_from(args);
 
+    let command = match matches.subcommand() {
+        ("import-opml", Some(sub_matches)) => Some(Command::ImportOpml(PathBuf::from(
+            sub_matches.value_of("FILE").unwrap(),
+        ))),
+        ("export-opml", _) => Some(Command::ExportOpml),
+        ("search", Some(sub_matches)) => Some(Command::Search(
+            sub_matches
+                .values_of("QUERY")
+                .unwrap()
+                .collect::<Vec<&str>>()
+                .join(" "),
+        )),
+        _ => None,
+    };
     let config_path = create_config_path(matches.value_of("config"))?;
     let config_file = read_config_file(&config_path)?;
     let cache_path = create_cache_path()?;
-    let sources = config_file
-        .sources
-        .unwrap_or_else(Vec::new)
+    let sources = config_file_sources(&config_file)
         .into_iter()
         .map(Arc::new)
         .collect();
@@ -134,6 +419,10 @@ where
         cache_path,
         update_interval,
         sources,
+        retention: config_file.retention.unwrap_or_default(),
+        download_dir: download_dir(config_file.download_dir.as_deref()),
+        opener: opener(config_file.opener.as_deref(), env::var("BROWSER").ok()),
+        command,
     })
 }
 
@@ -191,6 +480,10 @@ mod test {
                     .collect(),
             ),
             update_interval: Some(200),
+            retention: None,
+            feeds: None,
+            download_dir: None,
+            opener: None,
         };
         let config_file = read_config_file(Path::new("tests/feedrs/feedrs.toml"));
         assert!(config_file.is_ok());
@@ -209,20 +502,226 @@ mod test {
         assert!(config_file.is_err());
     }
 
+    #[test]
+    fn test_read_retention() {
+        let config_file: ConfigFile = toml::from_str("retention = \"forever\"").unwrap();
+        assert_eq!(config_file.retention, Some(Retention::Forever));
+        let config_file: ConfigFile = toml::from_str("retention = { days = 30 }").unwrap();
+        assert_eq!(config_file.retention, Some(Retention::Days(30)));
+        let config_file: ConfigFile = toml::from_str("retention = { per_source = 100 }").unwrap();
+        assert_eq!(config_file.retention, Some(Retention::PerSource(100)));
+    }
+
+    #[test]
+    fn test_retention_is_expired() {
+        let old = DateTime::parse_from_rfc3339("2000-01-01T00:00:00+00:00").ok();
+        let new = Some(Utc::now().into());
+        assert!(!Retention::Forever.is_expired(old));
+        assert!(!Retention::PerSource(1).is_expired(old));
+        assert!(Retention::Days(30).is_expired(old));
+        assert!(!Retention::Days(30).is_expired(new));
+        assert!(!Retention::Days(30).is_expired(None));
+    }
+
+    #[test]
+    fn test_download_dir() {
+        let home = env::var("HOME").unwrap();
+        assert_eq!(download_dir(Some("~")), PathBuf::from(&home));
+        assert_eq!(
+            download_dir(Some("~/Podcasts")),
+            PathBuf::from(format!("{}/Podcasts", home))
+        );
+        assert_eq!(download_dir(Some("~user/a")), PathBuf::from("~user/a"));
+        assert_eq!(download_dir(Some("/tmp")), PathBuf::from("/tmp"));
+    }
+
+    #[test]
+    fn test_opener() {
+        let browser = Some(String::from("firefox %s:w3m"));
+        assert_eq!(
+            opener(Some("qutebrowser  --target tab"), browser.clone()),
+            vec!["qutebrowser", "--target", "tab"]
+        );
+        assert_eq!(opener(None, browser), vec!["firefox", "%s"]);
+        assert_eq!(opener(None, Some(String::from(" "))), vec!["xdg-open"]);
+        assert_eq!(opener(None, None), vec!["xdg-open"]);
+    }
+
+    #[test]
+    fn test_split_command() {
+        assert_eq!(
+            split_command(r#"'/opt/My Browser/browser' --profile "work \"tab\"" a\ b ''"#),
+            vec![
+                "/opt/My Browser/browser",
+                "--profile",
+                "work \"tab\"",
+                "a b",
+                ""
+            ]
+        );
+        assert_eq!(
+            split_command(r#"open "C:\Apps" -u\"#),
+            vec!["open", "C:\\Apps", "-u"]
+        );
+        assert!(split_command("  ").is_empty());
+    }
+
+    #[test]
+    fn test_opener_args() {
+        let url = "https://example.com/1";
+        let args = |opener: &[&str]| {
+            let opener: Vec<String> = opener.iter().map(|arg| String::from(*arg)).collect();
+            opener_args(&opener, url)
+        };
+        assert_eq!(args(&["xdg-open"]), vec!["xdg-open", url]);
+        assert_eq!(
+            args(&["tmux", "new-window", "w3m {}"]),
+            vec!["tmux", "new-window", "w3m https://example.com/1"]
+        );
+        assert_eq!(
+            args(&["firefox", "--new-tab", "%s"]),
+            vec!["firefox", "--new-tab", url]
+        );
+    }
+
     #[test]
     fn test_config_file_arg() {
         let config = config(vec!["feedrs", "-c", "tests/feedrs/feedrs.toml"]).unwrap();
         let home = env::var("HOME").unwrap();
         let sources = ["source_1", "source_2", "source_3"]
             .iter()
-            .map(|x| Arc::new(String::from(*x)))
+            .map(|x| Arc::new(Source::new(String::from(*x))))
             .collect();
         let expected = Config {
             config_path: PathBuf::from("tests/feedrs/feedrs.toml"),
             cache_path: PathBuf::from(format!("{}/.cache/feedrs/cache.db", home)),
             update_interval: 200,
             sources,
+            retention: Retention::Forever,
+            download_dir: PathBuf::from(format!("{}/Downloads", home)),
+            opener: opener(None, env::var("BROWSER").ok()),
+            command: None,
         };
         assert_eq!(config, expected);
     }
+
+    #[test]
+    fn test_config_command() {
+        let import = config(vec![
+            "feedrs",
+            "-c",
+            "tests/feedrs/feedrs.toml",
+            "import-opml",
+            "tests/opml/subscriptions.opml",
+        ])
+        .unwrap();
+        assert_eq!(
+            import.command,
+            Some(Command::ImportOpml(PathBuf::from(
+                "tests/opml/subscriptions.opml"
+            )))
+        );
+        let search = config(vec![
+            "feedrs",
+            "-c",
+            "tests/feedrs/feedrs.toml",
+            "search",
+            "rust",
+            "async",
+        ])
+        .unwrap();
+        assert_eq!(
+            search.command,
+            Some(Command::Search(String::from("rust async")))
+        );
+    }
+
+    #[test]
+    fn test_read_feeds() {
+        let config_file: ConfigFile = toml::from_str(
+            "sources = ['source_1', 'source_2']
+
+            [[feeds]]
+            url = 'source_2'
+
+            [[feeds]]
+            url = 'source_3'
+            name = 'Source 3'
+            tags = ['tag']
+            update_interval = 3600
+            enabled = false
+            headers = { Authorization = 'Basic dXNlcjpwYXNz' }",
+        )
+        .unwrap();
+        let mut source_3 = Source::new(String::from("source_3"));
+        source_3.name = Some(String::from("Source 3"));
+        source_3.tags = vec![String::from("tag")];
+        source_3.update_interval = Some(3600);
+        source_3.enabled = false;
+        source_3.headers.insert(
+            String::from("Authorization"),
+            String::from("Basic dXNlcjpwYXNz"),
+        );
+        let expected = vec![
+            Source::new(String::from("source_1")),
+            Source::new(String::from("source_2")),
+            source_3,
+        ];
+        assert_eq!(config_file_sources(&config_file), expected);
+    }
+
+    #[test]
+    fn test_add_sources() {
+        let path = env::temp_dir().join("feedrs_add_sources.toml");
+        fs::copy("tests/feedrs/feedrs.toml", &path).unwrap();
+        let mut source_4 = Source::new(String::from("source_4"));
+        source_4.name = Some(String::from("Source 4"));
+        source_4.tags = vec![String::from("folder/sub_folder")];
+        let sources = vec![Source::new(String::from("source_1")), source_4.clone()];
+        assert_eq!(add_sources(&path, sources.clone()).unwrap(), 1);
+        assert_eq!(add_sources(&path, sources).unwrap(), 0);
+        let config_file = read_config_file(&path).unwrap();
+        assert_eq!(config_file.update_interval, Some(200));
+        assert_eq!(config_file.feeds, Some(vec![source_4]));
+        assert_eq!(config_file_sources(&config_file).len(), 4);
+    }
+
+    #[test]
+    fn test_add_sources_keeps_layout() {
+        let path = env::temp_dir().join("feedrs_add_sources_layout.toml");
+        let contents = "# Check every ten minutes
+update_interval = 600
+
+[[feeds]]
+# The blog of a friend
+url = 'source_1'
+tags = ['friends']
+[feeds.headers]
+Authorization = 'Basic dXNlcjpwYXNz'";
+        fs::write(&path, contents).unwrap();
+        let mut source_2 = Source::new(String::from("source_2"));
+        source_2
+            .headers
+            .insert(String::from("Cookie"), String::from("session=1"));
+        let source_3 = Source::new(String::from("source_3"));
+        let sources = vec![source_2.clone(), source_3.clone()];
+        assert_eq!(add_sources(&path, sources).unwrap(), 2);
+        let written = fs::read_to_string(&path).unwrap();
+        assert!(written.starts_with(contents));
+        let config_file = read_config_file(&path).unwrap();
+        assert_eq!(config_file.update_interval, Some(600));
+        let mut source_1 = Source::new(String::from("source_1"));
+        source_1.tags = vec![String::from("friends")];
+        source_1.headers.insert(
+            String::from("Authorization"),
+            String::from("Basic dXNlcjpwYXNz"),
+        );
+        assert_eq!(config_file.feeds, Some(vec![source_1, source_2, source_3]));
+        // The feeds written as an inline array are not changed
+        let contents = "feeds = [{ url = 'source_1' }]\n";
+        fs::write(&path, contents).unwrap();
+        let sources = vec![Source::new(String::from("source_2"))];
+        add_sources(&path, sources).unwrap_err();
+        assert_eq!(fs::read_to_string(&path).unwrap(), contents);
+    }
 }
@@ -1,5 +1,8 @@
-use chrono::{DateTime, FixedOffset};
-use sqlx::FromRow;
+use atom_syndication::Entry;
+use chrono::{DateTime, FixedOffset, NaiveDate, Utc, Weekday};
+use html2text::render::text_renderer::{RichAnnotation, TaggedLineElement};
+use reqwest::Url;
+use rss::{extension::syndication::UpdatePeriod, Channel, Item};
 use std::{
     cmp::Ordering,
     collections::{BTreeSet, HashMap},
@@ -7,7 +10,9 @@ use std::{
 };
 use syndication::Feed;
 
-#[derive(FromRow, PartialEq, Eq, Clone, Debug)]
+use crate::{json_feed::parse_json_feed, panics::catch_quietly};
+
+#[derive(PartialEq, Eq, Clone, Debug, Default)]
 pub struct Article {
     pub id: String,
     pub source: String,
@@ -15,20 +20,46 @@ pub struct Article {
     pub sub_title: String,
     pub content: String,
     pub date: Option<DateTime<FixedOffset>>,
+    pub read: bool,
+    pub starred: bool,
+    // Canonical url of the article
+    pub link: Option<String>,
+    pub authors: Vec<String>,
+    pub categories: Vec<String>,
+    // Url of the comments page
+    pub comments: Option<String>,
+    // Media attached to the article, like the episodes of a podcast
+    pub enclosures: Vec<Enclosure>,
+    // Links in the summary and content, in the order they appear
+    pub links: Vec<Link>,
+    // Html of the sub title and content, empty if they are plain text
+    pub sub_title_html: String,
+    pub content_html: String,
+}
+
+/// Link in the html of an article
+#[derive(PartialEq, Eq, Clone, Debug, Default)]
+pub struct Link {
+    pub url: String,
+    // Text of the link, can be empty for images
+    pub text: String,
+}
+
+/// Media file attached to an article
+#[derive(PartialEq, Eq, Clone, Debug, Default)]
+pub struct Enclosure {
+    pub url: String,
+    pub mime_type: Option<String>,
+    // Size in bytes
+    pub length: Option<u64>,
+    // Duration in seconds
+    pub duration: Option<u64>,
 }
 
 /// Partial order articles from newer to older, so we reverse the order of the date compare
 impl PartialOrd for Article {
     fn partial_cmp(&self, other: &Self) -> Option<std::cmp::Ordering> {
-        let date_ordering = self.date.partial_cmp(&other.date);
-        if date_ordering == Some(Ordering::Equal) {
-            let source_ordering = self.source.partial_cmp(&other.source);
-            if source_ordering == Some(Ordering::Equal) {
-                return self.id.partial_cmp(&other.id);
-            }
-            return source_ordering;
-        }
-        date_ordering
+        Some(self.cmp(other))
     }
 }
 
@@ -56,6 +87,9 @@ pub struct ArticleMap {
     // Those need to be Arc because they are shared references
     ids: HashMap<(String, String), Arc<Article>>,
     articles: BTreeSet<Arc<Article>>,
+    // Incremented by every change, the lists derived from the articles are computed again when
+    // it changes
+    version: u64,
 }
 
 /// Those functions grant access to the values inside the ArticleMap restricting mutability to
@@ -73,6 +107,7 @@ impl ArticleMap {
             }
         }
         self.articles.insert(Arc::clone(&article));
+        self.version += 1;
     }
 
     /// Get a reference to the article map's articles.
@@ -80,61 +115,256 @@ impl ArticleMap {
         &self.articles
     }
 
+    /// Number of changes of the articles
+    pub fn version(&self) -> u64 {
+        self.version
+    }
+
+    /// Returns the article with the id and source of the key
+    pub fn get(&self, key: &(String, String)) -> Option<Arc<Article>> {
+        self.ids.get(key).cloned()
+    }
+
     pub fn remove(&mut self, key: &(String, String)) -> Option<Arc<Article>> {
         let value = self.ids.remove(key);
         if let Some(value) = value {
             self.articles.remove(&value);
+            self.version += 1;
             return Some(value);
         }
         None
     }
 
-    /// Inserts a list of new elements and returns the Vec that where removed from the Vec
-    pub fn update_content(
-        &mut self,
-        content_update: &HashMap<(String, String), Article>,
-    ) -> HashMap<(String, String), Arc<Article>> {
-        let mut ret: HashMap<(String, String), Arc<Article>> = HashMap::new();
-        // Remove the values not found in the update
-        let keys: Vec<(String, String)> = self.ids.keys().cloned().collect();
-        for key in keys {
-            if !content_update.contains_key(&key) {
-                let value = self.remove(&key);
-                ret.insert(key, value.unwrap());
+    /// Sets the read flag of an article, returns the updated article if the flag was changed
+    pub fn set_read(&mut self, key: &(String, String), read: bool) -> Option<Arc<Article>> {
+        self.modify(key, |article| {
+            let changed = article.read != read;
+            article.read = read;
+            changed
+        })
+    }
+
+    /// Sets the starred flag of an article, returns the updated article if the flag was changed
+    pub fn set_starred(&mut self, key: &(String, String), starred: bool) -> Option<Arc<Article>> {
+        self.modify(key, |article| {
+            let changed = article.starred != starred;
+            article.starred = starred;
+            changed
+        })
+    }
+
+    /// Replaces an article with a modified copy if the closure returns true
+    fn modify<F>(&mut self, key: &(String, String), f: F) -> Option<Arc<Article>>
+    where
+        F: FnOnce(&mut Article) -> bool,
+    {
+        let mut article = (**self.ids.get(key)?).clone();
+        if !f(&mut article) {
+            return None;
+        }
+        self.insert(article);
+        self.ids.get(key).cloned()
+    }
+
+    /// Merges the fetched articles of a source keeping the user state of the articles already in
+    /// the map. Only the articles of the source are touched, the ones not found in the update are
+    /// kept and removed only when they expire. The version changes even without articles, since
+    /// the information of the feed can be new.
+    pub fn update_source(&mut self, source: &str, articles: &[Article]) {
+        self.version += 1;
+        for article in articles.iter().filter(|article| article.source == source) {
+            let mut article = article.clone();
+            // The fetched articles don't know the user state, keep the one we already have
+            if let Some(old_article) = self.ids.get(&(article.id.clone(), article.source.clone())) {
+                article.read = old_article.read;
+                article.starred = old_article.starred;
             }
+            self.insert(article);
         }
-        content_update.values().for_each(|x| self.insert(x.clone()));
-        ret
     }
 }
 
+/// Hints of the publisher on when to request the feed
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct UpdateHints {
+    // Seconds the feed can be cached
+    pub ttl: Option<u64>,
+    // Hours of the day in GMT when the feed is not updated
+    pub skip_hours: Vec<u32>,
+    // Days of the week in GMT when the feed is not updated
+    pub skip_days: Vec<Weekday>,
+    // Seconds between the updates of the feed, from the syndication extension
+    pub update_period: Option<u64>,
+}
+
+/// Information on the feed itself, published in the channel of RSS and in the feed of Atom
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct FeedMetadata {
+    pub title: Option<String>,
+    pub description: Option<String>,
+    // Url of the web site of the feed
+    pub site_link: Option<String>,
+    pub language: Option<String>,
+    // Url of the icon or of the logo
+    pub icon: Option<String>,
+    // Program used to generate the feed
+    pub generator: Option<String>,
+}
+
+/// Articles of a feed with the information on the feed itself
+#[derive(Clone, Debug, PartialEq)]
+pub struct ParsedFeed {
+    pub articles: Vec<Article>,
+    pub hints: UpdateHints,
+    pub metadata: FeedMetadata,
+}
+
 /// Parses an RSS or Atom iterm/feed into a collection of Articles
-pub fn parse_content(source: &str, content: String) -> anyhow::Result<Vec<Article>> {
-    let feed = content.parse::<Feed>().unwrap();
-    Ok(match feed {
+/// Parses the feed, the format is detected from the content type or from the body when the
+/// content type is missing or generic
+pub fn parse_content(
+    source: &str,
+    content_type: Option<&str>,
+    content: String,
+) -> anyhow::Result<ParsedFeed> {
+    let mut feed = if is_json(content_type, &content) {
+        parse_json_feed(source, &content)?
+    } else {
+        parse_xml_feed(source, content)?
+    };
+    // The articles are identified by id and source, so they can't share an empty id
+    for article in feed
+        .articles
+        .iter_mut()
+        .filter(|article| article.id.is_empty())
+    {
+        article.id = fallback_id(article);
+    }
+    Ok(feed)
+}
+
+/// Identifier of the articles without id or link: the title with the date, or the hash of the text
+fn fallback_id(article: &Article) -> String {
+    match article.date {
+        Some(date) if !article.title.is_empty() => {
+            format!("{} {}", article.title, date.to_rfc3339())
+        }
+        _ => {
+            let text = [&article.title, &article.sub_title, &article.content];
+            format!(
+                "{:016x}",
+                fnv1a(text.iter().flat_map(|text| text.bytes().chain(Some(0))))
+            )
+        }
+    }
+}
+
+/// 64 bit FNV-1a hash, stable between versions and runs unlike the standard hasher
+fn fnv1a(bytes: impl Iterator<Item = u8>) -> u64 {
+    bytes.fold(0xcbf2_9ce4_8422_2325, |hash, byte| {
+        (hash ^ u64::from(byte)).wrapping_mul(0x0000_0100_0000_01b3)
+    })
+}
+
+/// Checks if the feed is a JSON Feed, served as `application/feed+json` or `application/json`
+fn is_json(content_type: Option<&str>, content: &str) -> bool {
+    let mime = content_type
+        .and_then(|content_type| content_type.split(';').next())
+        .map(|mime| mime.trim().to_ascii_lowercase());
+    match mime.as_deref() {
+        Some("application/feed+json") | Some("application/json") => true,
+        // Many servers send the feeds as plain text or a generic type
+        _ => content.trim_start().starts_with('{'),
+    }
+}
+
+/// Parses an RSS or Atom feed, RSS 1.0 (RDF) and 0.9x included
+fn parse_xml_feed(source: &str, content: String) -> anyhow::Result<ParsedFeed> {
+    let feed = content
+        .parse::<Feed>()
+        .map_err(|err| anyhow::anyhow!("invalid feed: {}", err))?;
+    let hints = match &feed {
+        Feed::Atom(_) => UpdateHints::default(),
+        Feed::RSS(channel) => update_hints(channel),
+    };
+    let metadata = match &feed {
+        Feed::Atom(feed) => FeedMetadata {
+            title: non_empty(Some(feed.title())),
+            description: non_empty(feed.subtitle()),
+            site_link: non_empty(
+                feed.links()
+                    .iter()
+                    .find(|link| link.rel() == "alternate")
+                    .map(|link| link.href()),
+            ),
+            // The xml:lang attribute is not parsed
+            language: None,
+            icon: non_empty(feed.icon().or_else(|| feed.logo())),
+            generator: non_empty(feed.generator().map(|generator| generator.value())),
+        },
+        Feed::RSS(channel) => channel_metadata(channel),
+    };
+    let articles = match feed {
         // Atom feed
         Feed::Atom(feed) => feed
             .entries()
             .iter()
             .map(|entry| {
-                let content = if let Some(content) = entry.content() {
-                    parse_html(content.value().unwrap_or(""))
-                } else {
-                    String::from("")
-                };
-                let update = if let Ok(date) = DateTime::parse_from_rfc3339(entry.updated()) {
-                    Some(date)
-                } else {
-                    None
+                let html = entry
+                    .content()
+                    .and_then(|content| content.value())
+                    .unwrap_or("");
+                let content = parse_html(html);
+                let summary = entry.summary().unwrap_or("");
+                let update = DateTime::parse_from_rfc3339(entry.updated()).ok();
+                // Fall back to the link of the entry
+                let link = entry_link(entry, "alternate");
+                let links = parse_links(link.as_deref(), &[summary, html]);
+                let id = match entry.id().trim() {
+                    "" => link.clone().unwrap_or_default(),
+                    id => String::from(id),
                 };
-                return Article {
-                    id: String::from(entry.id()),
+                Article {
+                    id,
                     source: String::from(source),
                     title: String::from(entry.title()),
-                    sub_title: parse_html(entry.summary().unwrap_or("")),
+                    sub_title: parse_html(summary),
                     content,
                     date: update,
-                };
+                    read: false,
+                    starred: false,
+                    link,
+                    authors: entry
+                        .authors()
+                        .iter()
+                        .map(|author| author.name().trim())
+                        .filter(|name| !name.is_empty())
+                        .map(String::from)
+                        .collect(),
+                    categories: entry
+                        .categories()
+                        .iter()
+                        .map(|category| category.label().unwrap_or_else(|| category.term()))
+                        .filter(|category| !category.trim().is_empty())
+                        .map(|category| String::from(category.trim()))
+                        .collect(),
+                    // Threading extension, the replies to the entry
+                    comments: entry_link(entry, "replies"),
+                    enclosures: entry
+                        .links()
+                        .iter()
+                        .filter(|link| link.rel() == "enclosure" && !link.href().trim().is_empty())
+                        .map(|link| Enclosure {
+                            url: String::from(link.href().trim()),
+                            mime_type: non_empty(link.mime_type()),
+                            length: link.length().and_then(|length| length.trim().parse().ok()),
+                            duration: None,
+                        })
+                        .collect(),
+                    links,
+                    sub_title_html: String::from(summary),
+                    content_html: String::from(html),
+                }
             })
             .collect(),
         // RSS feed
@@ -142,38 +372,393 @@ pub fn parse_content(source: &str, content: String) -> anyhow::Result<Vec<Articl
             .items()
             .iter()
             .map(|item| {
-                let id = if let Some(guid) = item.guid() {
-                    String::from(guid.value())
-                } else {
-                    String::from("")
-                };
-                let content = if let Some(content) = item.content() {
-                    parse_html(content)
-                } else {
-                    String::from("")
-                };
-                let update = if let Some(date) = item.pub_date() {
-                    DateTime::parse_from_rfc2822(date).ok()
-                } else {
-                    None
-                };
-                return Article {
-                    id,
+                let html = item.content().unwrap_or("");
+                let description = item.description().unwrap_or("");
+                let link = item.link().map(|link| String::from(link.trim()));
+                Article {
+                    id: item_id(item),
                     source: String::from(source),
                     title: String::from(item.title().unwrap_or("")),
-                    sub_title: parse_html(item.description().unwrap_or("")),
-                    content,
-                    date: update,
-                };
+                    sub_title: parse_html(description),
+                    content: parse_html(html),
+                    date: item_date(item),
+                    read: false,
+                    starred: false,
+                    links: parse_links(link.as_deref(), &[description, html]),
+                    link,
+                    authors: item_authors(item),
+                    categories: item_categories(item),
+                    comments: item
+                        .comments()
+                        .map(|comments| String::from(comments.trim())),
+                    enclosures: item_enclosures(item),
+                    sub_title_html: String::from(description),
+                    content_html: String::from(html),
+                }
             })
             .collect(),
+    };
+    Ok(ParsedFeed {
+        articles,
+        hints,
+        metadata,
     })
 }
 
-/// Parses an html formated text into a pretty representation easy to view in the terminal
-fn parse_html(html: &str) -> String {
-    // Set max line-length to 160 (80 * 2) this could be better
-    html2text::from_read(html.as_bytes(), 160)
+/// Reads the information on the feed from the RSS channel, the RSS 1.0 feeds use Dublin Core
+fn channel_metadata(channel: &Channel) -> FeedMetadata {
+    let dublin_core = channel.dublin_core_ext();
+    FeedMetadata {
+        title: non_empty(Some(channel.title())),
+        description: non_empty(Some(channel.description())),
+        site_link: non_empty(Some(channel.link())),
+        language: non_empty(channel.language()).or_else(|| {
+            non_empty(
+                dublin_core
+                    .and_then(|dublin_core| dublin_core.languages().first().map(String::as_str)),
+            )
+        }),
+        icon: non_empty(channel.image().map(|image| image.url())),
+        generator: non_empty(channel.generator()),
+    }
+}
+
+/// Trimmed text, None if it's empty
+pub fn non_empty(text: Option<&str>) -> Option<String> {
+    text.map(str::trim)
+        .filter(|text| !text.is_empty())
+        .map(String::from)
+}
+
+/// Identifier of an RSS item: the guid, the Dublin Core identifier or the link. The RSS 1.0 items
+/// are identified by their `rdf:about`, which should be the same as the link.
+fn item_id(item: &Item) -> String {
+    item.guid()
+        .map(|guid| guid.value())
+        .or_else(|| {
+            item.dublin_core_ext()
+                .and_then(|dublin_core| dublin_core.identifiers().first())
+                .map(String::as_str)
+        })
+        .or_else(|| item.link())
+        .map(|id| String::from(id.trim()))
+        .unwrap_or_default()
+}
+
+/// Authors of an RSS item, from the `author` element and the Dublin Core creators
+fn item_authors(item: &Item) -> Vec<String> {
+    let creators = item
+        .dublin_core_ext()
+        .map(|dublin_core| dublin_core.creators())
+        .unwrap_or_default();
+    let mut authors: Vec<String> = Vec::new();
+    for author in item
+        .author()
+        .into_iter()
+        .chain(creators.iter().map(String::as_str))
+    {
+        let author = author.trim();
+        if !author.is_empty() && !authors.iter().any(|other| other == author) {
+            authors.push(String::from(author));
+        }
+    }
+    authors
+}
+
+/// Categories of an RSS item, the Dublin Core subjects included
+fn item_categories(item: &Item) -> Vec<String> {
+    let subjects = item
+        .dublin_core_ext()
+        .map(|dublin_core| dublin_core.subjects())
+        .unwrap_or_default();
+    let mut categories: Vec<String> = Vec::new();
+    let names = item.categories().iter().map(|category| category.name());
+    for category in names.chain(subjects.iter().map(String::as_str)) {
+        let category = category.trim();
+        if !category.is_empty() && !categories.iter().any(|other| other == category) {
+            categories.push(String::from(category));
+        }
+    }
+    categories
+}
+
+/// Enclosure of an RSS item, with the duration of the iTunes extension
+fn item_enclosures(item: &Item) -> Vec<Enclosure> {
+    let duration = item
+        .itunes_ext()
+        .and_then(|itunes| itunes.duration())
+        .and_then(parse_duration);
+    item.enclosure()
+        .filter(|enclosure| !enclosure.url().trim().is_empty())
+        .map(|enclosure| Enclosure {
+            url: String::from(enclosure.url().trim()),
+            mime_type: non_empty(Some(enclosure.mime_type())),
+            // Some publishers write 0 when the size is unknown
+            length: enclosure
+                .length()
+                .trim()
+                .parse()
+                .ok()
+                .filter(|length| *length > 0),
+            duration,
+        })
+        .into_iter()
+        .collect()
+}
+
+/// Parses an iTunes duration in seconds, written as `HH:MM:SS`, `MM:SS` or the number of seconds
+pub fn parse_duration(duration: &str) -> Option<u64> {
+    let parts = duration
+        .trim()
+        .split(':')
+        .map(|part| part.parse::<u64>().ok())
+        .collect::<Option<Vec<u64>>>()?;
+    if parts.is_empty() || parts.len() > 3 {
+        return None;
+    }
+    Some(parts.iter().fold(0, |total, part| total * 60 + part))
+}
+
+/// Url of the first link of the Atom entry with the relation
+fn entry_link(entry: &Entry, rel: &str) -> Option<String> {
+    entry
+        .links()
+        .iter()
+        .find(|link| link.rel() == rel)
+        .map(|link| String::from(link.href().trim()))
+}
+
+/// Date of an RSS item, RSS 1.0 feeds use the Dublin Core date instead of `pubDate`
+fn item_date(item: &Item) -> Option<DateTime<FixedOffset>> {
+    item.pub_date()
+        .and_then(|date| DateTime::parse_from_rfc2822(date.trim()).ok())
+        .or_else(|| {
+            item.dublin_core_ext()?
+                .dates()
+                .iter()
+                .find_map(|date| parse_w3c_date(date))
+        })
+}
+
+/// Parses a W3C date time as used by Dublin Core, the dates without a time are at midnight UTC
+fn parse_w3c_date(date: &str) -> Option<DateTime<FixedOffset>> {
+    let date = date.trim();
+    if let Ok(date) = DateTime::parse_from_rfc3339(date) {
+        return Some(date);
+    }
+    // The seconds are optional
+    let offset_date = match date.strip_suffix('Z') {
+        Some(date) => format!("{}+00:00", date),
+        None => String::from(date),
+    };
+    if let Ok(date) = DateTime::parse_from_str(&offset_date, "%Y-%m-%dT%H:%M%:z") {
+        return Some(date);
+    }
+    let date = NaiveDate::parse_from_str(date, "%Y-%m-%d").ok()?;
+    Some(DateTime::<Utc>::from_naive_utc_and_offset(date.and_hms_opt(0, 0, 0)?, Utc).into())
+}
+
+/// Reads the hints of the channel on when to request the feed
+fn update_hints(channel: &Channel) -> UpdateHints {
+    let update_period = channel.syndication_ext().map(|syndication| {
+        let period = match syndication.period() {
+            UpdatePeriod::HOURLY => 60 * 60,
+            UpdatePeriod::DAILY => 24 * 60 * 60,
+            UpdatePeriod::WEEKLY => 7 * 24 * 60 * 60,
+            UpdatePeriod::MONTHLY => 30 * 24 * 60 * 60,
+            UpdatePeriod::YEARLY => 365 * 24 * 60 * 60,
+        };
+        period / u64::from(syndication.frequency().max(1))
+    });
+    UpdateHints {
+        ttl: channel
+            .ttl()
+            .and_then(|ttl| ttl.trim().parse::<u64>().ok())
+            .map(|minutes| minutes * 60),
+        skip_hours: channel
+            .skip_hours()
+            .iter()
+            .filter_map(|hour| hour.trim().parse().ok())
+            // Some publishers use 24 for midnight
+            .map(|hour: u32| hour % 24)
+            .collect(),
+        skip_days: channel
+            .skip_days()
+            .iter()
+            .filter_map(|day| day.trim().parse().ok())
+            .collect(),
+        update_period,
+    }
+}
+
+/// Extracts the links of the html texts, without duplicates. The relative urls are resolved
+/// against the link of the article.
+pub fn parse_links(base: Option<&str>, html: &[&str]) -> Vec<Link> {
+    let base = base.and_then(|base| Url::parse(base).ok());
+    let mut links: Vec<Link> = Vec::new();
+    let mut add = |link: Option<Link>| {
+        let link = match link {
+            Some(link) => link,
+            None => return,
+        };
+        let url = match &base {
+            Some(base) => base.join(link.url.trim()).ok().map(String::from),
+            None => Url::parse(link.url.trim()).ok().map(String::from),
+        };
+        if let Some(url) = url {
+            if !links.iter().any(|link| link.url == url) {
+                links.push(Link {
+                    url,
+                    text: String::from(link.text.trim()),
+                });
+            }
+        }
+    };
+    for html in html {
+        // A link wrapped on several lines is split in multiple strings
+        let mut current: Option<Link> = None;
+        // html2text panics on some documents, their links are skipped
+        let lines = catch_quietly(|| html2text::from_read_rich(html.as_bytes(), 160));
+        for line in lines.unwrap_or_default() {
+            for element in line.iter() {
+                let string = match element {
+                    TaggedLineElement::Str(string) => string,
+                    _ => continue,
+                };
+                let url = string.tag.iter().find_map(|annotation| match annotation {
+                    RichAnnotation::Link(url) => Some(url),
+                    _ => None,
+                });
+                match (url, &mut current) {
+                    (Some(url), Some(link)) if link.url == *url => link.text.push_str(&string.s),
+                    (Some(url), _) => {
+                        add(current.take());
+                        current = Some(Link {
+                            url: url.clone(),
+                            text: string.s.clone(),
+                        });
+                    }
+                    (None, _) => add(current.take()),
+                }
+            }
+            if let Some(link) = &mut current {
+                link.text.push(' ');
+            }
+        }
+        add(current);
+    }
+    links
+}
+
+/// Width of the plain text of the html, larger than any paragraph so it's never wrapped
+const UNWRAPPED_WIDTH: usize = 100_000;
+/// Width of the plain text of the html with tables
+const TABLE_WIDTH: usize = 80;
+
+/// Urls of the feeds an html page links as alternate, resolved against the url of the page
+pub fn feed_links(base: &str, html: &str) -> Vec<String> {
+    const FEED_TYPES: [&str; 3] = [
+        "application/rss+xml",
+        "application/atom+xml",
+        "application/feed+json",
+    ];
+    let base = match Url::parse(base) {
+        Ok(base) => base,
+        Err(_) => return Vec::new(),
+    };
+    // Lowercase ascii keeps the positions of the original html
+    let lower = html.to_ascii_lowercase();
+    let mut links = Vec::new();
+    let mut position = 0;
+    while let Some(start) = lower[position..].find("<link") {
+        let start = position + start + "<link".len();
+        let end = lower[start..]
+            .find('>')
+            .map_or(lower.len(), |end| start + end);
+        position = end;
+        if !lower[start..].starts_with(|c: char| c.is_ascii_whitespace()) {
+            continue;
+        }
+        let attributes = tag_attributes(&html[start..end]);
+        let attribute = |name: &str| {
+            attributes
+                .iter()
+                .find(|(attribute, _)| attribute == name)
+                .map(|(_, value)| value.trim())
+        };
+        let is_alternate = attribute("rel").is_some_and(|rel| {
+            rel.split_ascii_whitespace()
+                .any(|rel| rel.eq_ignore_ascii_case("alternate"))
+        });
+        let is_feed = attribute("type").is_some_and(|mime_type| {
+            FEED_TYPES
+                .iter()
+                .any(|feed_type| mime_type.eq_ignore_ascii_case(feed_type))
+        });
+        if let Some(href) = attribute("href").filter(|_| is_alternate && is_feed) {
+            if let Ok(url) = base.join(href) {
+                let url = String::from(url);
+                if !links.contains(&url) {
+                    links.push(url);
+                }
+            }
+        }
+    }
+    links
+}
+
+/// Attributes of an html tag with their lowercase name, the values are quoted or not
+fn tag_attributes(tag: &str) -> Vec<(String, String)> {
+    let mut attributes = Vec::new();
+    let mut rest = tag.trim_start();
+    while !rest.is_empty() {
+        let name_end = rest
+            .find(|c: char| c == '=' || c.is_whitespace())
+            .unwrap_or(rest.len());
+        let name = rest[..name_end].trim_matches('/').to_ascii_lowercase();
+        rest = rest[name_end..].trim_start();
+        let value = match rest.strip_prefix('=') {
+            Some(value) => {
+                let value = value.trim_start();
+                let (value, next) = match value.chars().next() {
+                    Some(quote @ ('"' | '\'')) => {
+                        let value = &value[1..];
+                        let end = value.find(quote).unwrap_or(value.len());
+                        (&value[..end], value.get(end + 1..).unwrap_or(""))
+                    }
+                    _ => {
+                        let end = value.find(char::is_whitespace).unwrap_or(value.len());
+                        (&value[..end], &value[end..])
+                    }
+                };
+                rest = next.trim_start();
+                value
+            }
+            None => "",
+        };
+        if !name.is_empty() {
+            attributes.push((name, value.replace("&amp;", "&")));
+        }
+    }
+    attributes
+}
+
+/// Parses an html formated text into a pretty representation easy to view in the terminal. The
+/// lines are not wrapped, the view wraps them at its width.
+pub fn parse_html(html: &str) -> String {
+    // html2text spreads the tables on the whole width, they are laid out for a usual terminal
+    let width = if html.to_ascii_lowercase().contains("<table") {
+        TABLE_WIDTH
+    } else {
+        UNWRAPPED_WIDTH
+    };
+    // html2text panics on some documents, they are shown as they are
+    catch_quietly(|| html2text::from_read(html.as_bytes(), width))
+        .unwrap_or_else(|| String::from(html))
+        .lines()
+        .map(str::trim_end)
+        .collect::<Vec<&str>>()
+        .join("\n")
         .trim()
         .to_string()
 }
@@ -182,6 +767,334 @@ fn parse_html(html: &str) -> String {
 mod test {
     use super::*;
 
+    /// Article parsed from the fixtures
+    fn article(id: &str, title: &str, sub_title: &str, content: &str, date: &str) -> Article {
+        Article {
+            id: String::from(id),
+            source: String::from("source"),
+            title: String::from(title),
+            sub_title: String::from(sub_title),
+            content: String::from(content),
+            date: DateTime::parse_from_rfc3339(date).ok(),
+            read: false,
+            starred: false,
+            ..Default::default()
+        }
+    }
+
+    fn parse_fixture(name: &str) -> ParsedFeed {
+        let content = std::fs::read_to_string(format!("tests/feeds/{}", name)).unwrap();
+        parse_content("source", None, content).unwrap()
+    }
+
+    #[test]
+    fn test_parse_content_rdf() {
+        let feed = parse_fixture("rdf.xml");
+        assert_eq!(
+            feed.articles,
+            vec![
+                Article {
+                    link: Some(String::from("https://example.org/articles/2")),
+                    authors: vec![String::from("Jane Doe"), String::from("Richard Roe")],
+                    sub_title_html: String::from("Second <b>article</b>"),
+                    content_html: String::from("<p>Content of the second article</p>"),
+                    ..article(
+                        "doi:10.1000/2",
+                        "Second",
+                        "Second article",
+                        "Content of the second article",
+                        "2021-03-02T09:30:00+01:00"
+                    )
+                },
+                Article {
+                    link: Some(String::from("https://example.org/articles/1")),
+                    authors: vec![String::from("John Doe")],
+                    sub_title_html: String::from("First article"),
+                    ..article(
+                        "https://example.org/articles/1",
+                        "First",
+                        "First article",
+                        "",
+                        "2021-03-01T00:00:00Z"
+                    )
+                },
+            ]
+        );
+        // Twice every hour
+        assert_eq!(feed.hints.update_period, Some(30 * 60));
+        assert_eq!(
+            feed.metadata,
+            FeedMetadata {
+                title: Some(String::from("RDF")),
+                description: Some(String::from("RSS 1.0 feed")),
+                site_link: Some(String::from("https://example.org/")),
+                language: Some(String::from("en")),
+                icon: None,
+                generator: None,
+            }
+        );
+    }
+
+    #[test]
+    fn test_parse_content_rss_091() {
+        let feed = parse_fixture("rss091.xml");
+        assert_eq!(
+            feed.articles,
+            vec![
+                Article {
+                    link: Some(String::from("https://example.net/2.html")),
+                    sub_title_html: String::from("Second <i>article</i>"),
+                    ..article(
+                        "https://example.net/2.html",
+                        "Second",
+                        "Second article",
+                        "",
+                        ""
+                    )
+                },
+                Article {
+                    link: Some(String::from("https://example.net/1.html")),
+                    sub_title_html: String::from("First article"),
+                    ..article(
+                        "https://example.net/1.html",
+                        "First",
+                        "First article",
+                        "",
+                        ""
+                    )
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn test_parse_content_rss_2() {
+        let feed = parse_fixture("rss2.xml");
+        assert_eq!(
+            feed.articles,
+            vec![
+                Article {
+                    link: Some(String::from("https://example.com/2")),
+                    authors: vec![String::from("Jane Doe")],
+                    categories: vec![String::from("rust"), String::from("release")],
+                    comments: Some(String::from("https://example.com/2#comments")),
+                    enclosures: vec![Enclosure {
+                        url: String::from("https://example.com/2.mp3"),
+                        mime_type: Some(String::from("audio/mpeg")),
+                        length: Some(1024),
+                        duration: Some(3723),
+                    }],
+                    sub_title_html: String::from("<p>Second article</p>"),
+                    content_html: String::from("<p>Content of the <b>second</b> article</p>"),
+                    ..article(
+                        "tag:example.com,2021:2",
+                        "Second",
+                        "Second article",
+                        "Content of the second article",
+                        "2021-03-02T09:30:00+01:00"
+                    )
+                },
+                Article {
+                    link: Some(String::from("https://example.com/1")),
+                    authors: vec![String::from("john@example.com (John Doe)")],
+                    sub_title_html: String::from("First article"),
+                    ..article(
+                        "https://example.com/1",
+                        "First",
+                        "First article",
+                        "",
+                        "2021-03-01T08:00:00Z"
+                    )
+                },
+            ]
+        );
+        assert_eq!(
+            feed.metadata,
+            FeedMetadata {
+                title: Some(String::from("RSS 2.0")),
+                description: Some(String::from("RSS 2.0 feed")),
+                site_link: Some(String::from("https://example.com/")),
+                language: Some(String::from("en-us")),
+                icon: Some(String::from("https://example.com/logo.png")),
+                generator: Some(String::from("Hugo")),
+            }
+        );
+    }
+
+    #[test]
+    fn test_parse_content_atom() {
+        let feed = parse_fixture("atom.xml");
+        assert_eq!(
+            feed.articles,
+            vec![
+                Article {
+                    link: Some(String::from("https://example.com/2")),
+                    authors: vec![String::from("John Doe")],
+                    categories: vec![String::from("Rust"), String::from("release")],
+                    comments: Some(String::from("https://example.com/2#comments")),
+                    enclosures: vec![Enclosure {
+                        url: String::from("https://example.com/2.ogg"),
+                        mime_type: Some(String::from("audio/ogg")),
+                        length: Some(2048),
+                        duration: None,
+                    }],
+                    sub_title_html: String::from("Second article"),
+                    content_html: String::from("<p>Content of the second article</p>"),
+                    ..article(
+                        "urn:uuid:1225c695-cfb8-4ebb-aaaa-80da344efa6a",
+                        "Second",
+                        "Second article",
+                        "Content of the second article",
+                        "2021-03-02T09:30:00+01:00"
+                    )
+                },
+                Article {
+                    link: Some(String::from("https://example.com/1")),
+                    sub_title_html: String::from("First article"),
+                    ..article(
+                        "urn:uuid:1225c695-cfb8-4ebb-aaaa-80da344efa6b",
+                        "First",
+                        "First article",
+                        "",
+                        "2021-03-01T08:00:00Z"
+                    )
+                },
+            ]
+        );
+        assert_eq!(
+            feed.metadata,
+            FeedMetadata {
+                title: Some(String::from("Atom 1.0")),
+                description: Some(String::from("Atom 1.0 feed")),
+                site_link: Some(String::from("https://example.com/")),
+                language: None,
+                icon: Some(String::from("https://example.com/favicon.ico")),
+                generator: Some(String::from("Generator")),
+            }
+        );
+    }
+
+    #[test]
+    fn test_feed_links() {
+        let html = r#"<html><head>
+            <link rel="stylesheet" href="/style.css">
+            <LINK REL="alternate" TYPE="application/rss+xml" HREF="/feed.xml?a=1&amp;b=2">
+            <link href='https://example.com/atom.xml' type='application/atom+xml' rel='alternate'/>
+            <link rel=alternate type=application/feed+json href=feed.json>
+            <link rel="alternate" type="text/html" hreflang="fr" href="/fr/">
+            <link rel="alternate" type="application/rss+xml" href="/feed.xml?a=1&b=2">
+            <linked rel="alternate" type="application/rss+xml" href="/other.xml">
+        </head></html>"#;
+        assert_eq!(
+            feed_links("https://example.com/blog/post", html),
+            vec![
+                "https://example.com/feed.xml?a=1&b=2",
+                "https://example.com/atom.xml",
+                "https://example.com/blog/feed.json",
+            ]
+        );
+        assert!(feed_links("not an url", html).is_empty());
+        assert!(feed_links("https://example.com/", "<p>No feed</p><link").is_empty());
+    }
+
+    #[test]
+    fn test_parse_links() {
+        let summary = "<p>See <a href=\"/about\">the about page</a></p>";
+        let content = "<p>A <a href=\"https://example.org/long\">link with a long text that is \
+            wrapped by the renderer on two lines since it's longer than the maximum width of the \
+            lines</a>, <a href=\"about\">again</a> and <a href=\"#top\">top</a></p>";
+        let link = |url: &str, text: &str| Link {
+            url: String::from(url),
+            text: String::from(text),
+        };
+        assert_eq!(
+            parse_links(Some("https://example.com/posts/1"), &[summary, content]),
+            vec![
+                link("https://example.com/about", "the about page"),
+                link(
+                    "https://example.org/long",
+                    "link with a long text that is wrapped by the renderer on two lines since \
+                    it's longer than the maximum width of the lines"
+                ),
+                link("https://example.com/posts/about", "again"),
+                link("https://example.com/posts/1#top", "top"),
+            ]
+        );
+        // Relative urls are skipped without a base
+        assert_eq!(parse_links(None, &[summary]), Vec::new());
+    }
+
+    #[test]
+    fn test_parse_duration() {
+        assert_eq!(parse_duration("01:02:03"), Some(3723));
+        assert_eq!(parse_duration("2:03"), Some(123));
+        assert_eq!(parse_duration(" 90 "), Some(90));
+        assert_eq!(parse_duration("1:2:3:4"), None);
+        assert_eq!(parse_duration("one hour"), None);
+        assert_eq!(parse_duration(""), None);
+    }
+
+    #[test]
+    fn test_parse_content_no_guid() {
+        let feed = parse_fixture("no_guid.xml");
+        let ids: Vec<&str> = feed
+            .articles
+            .iter()
+            .map(|article| article.id.as_str())
+            .collect();
+        assert_eq!(
+            ids[..4],
+            [
+                "https://example.com/news/release-2-1",
+                "https://example.com/news/release-2-0",
+                "Maintenance window 2021-02-26T18:00:00+00:00",
+                "Maintenance window 2021-02-19T18:00:00+00:00",
+            ]
+        );
+        // All the articles survive
+        let mut article_map = ArticleMap::default();
+        article_map.update_source("source", &feed.articles);
+        assert_eq!(article_map.articles().len(), 6);
+        // The ids are the same in the next fetch
+        assert_eq!(parse_fixture("no_guid.xml").articles, feed.articles);
+    }
+
+    #[test]
+    fn test_fallback_id() {
+        let mut article = article("", "title", "sub_title", "content", "");
+        let id = fallback_id(&article);
+        assert_eq!(id.len(), 16);
+        // The hash covers all the text
+        article.content = String::from("other content");
+        assert_ne!(fallback_id(&article), id);
+        // The title is not enough without the date
+        article.date = DateTime::parse_from_rfc3339("2021-03-01T08:00:00Z").ok();
+        assert_eq!(fallback_id(&article), "title 2021-03-01T08:00:00+00:00");
+    }
+
+    #[test]
+    fn test_fnv1a() {
+        assert_eq!(fnv1a("".bytes()), 0xcbf2_9ce4_8422_2325);
+        assert_eq!(fnv1a("a".bytes()), 0xaf63_dc4c_8601_ec8c);
+        assert_eq!(fnv1a("foobar".bytes()), 0x8594_4171_f739_67e8);
+    }
+
+    #[test]
+    fn test_parse_w3c_date() {
+        let expected = DateTime::parse_from_rfc3339("2021-03-01T08:30:00+01:00").ok();
+        assert_eq!(parse_w3c_date("2021-03-01T08:30:00+01:00"), expected);
+        assert_eq!(parse_w3c_date(" 2021-03-01T08:30+01:00 "), expected);
+        assert_eq!(
+            parse_w3c_date("2021-03-01T07:30Z"),
+            DateTime::parse_from_rfc3339("2021-03-01T07:30:00Z").ok()
+        );
+        assert_eq!(
+            parse_w3c_date("2021-03-01"),
+            DateTime::parse_from_rfc3339("2021-03-01T00:00:00Z").ok()
+        );
+        assert_eq!(parse_w3c_date("March 2021"), None);
+    }
+
     #[test]
     fn test_article_map_ordering_date_none() {
         let article_1 = Article {
@@ -191,6 +1104,9 @@ mod test {
             sub_title: "sub_title".to_owned(),
             content: "content".to_owned(),
             date: None,
+            read: false,
+            starred: false,
+            ..Default::default()
         };
         let mut article_2 = article_1.clone();
         article_2.id = "2".to_owned();
@@ -209,6 +1125,145 @@ mod test {
         assert_eq!(expected, result);
     }
 
+    #[test]
+    fn test_article_map_set_read() {
+        let article = Article {
+            id: "1".to_owned(),
+            source: "source".to_owned(),
+            title: "title".to_owned(),
+            sub_title: "sub_title".to_owned(),
+            content: "content".to_owned(),
+            date: None,
+            read: false,
+            starred: false,
+            ..Default::default()
+        };
+        let key = (article.id.clone(), article.source.clone());
+        let mut article_map: ArticleMap = Default::default();
+        article_map.insert(article.clone());
+        assert!(article_map.set_read(&key, true).is_some());
+        assert!(article_map.set_read(&key, true).is_none());
+        assert_eq!(article_map.articles().len(), 1);
+        assert!(article_map.articles().iter().next().unwrap().read);
+        // The update keeps the read flag
+        article_map.update_source("source", &[article]);
+        assert!(article_map.articles().iter().next().unwrap().read);
+    }
+
+    #[test]
+    fn test_article_map_version() {
+        let article = Article {
+            id: "1".to_owned(),
+            source: "source".to_owned(),
+            ..Default::default()
+        };
+        let key = (article.id.clone(), article.source.clone());
+        let mut article_map: ArticleMap = Default::default();
+        article_map.insert(article);
+        let version = article_map.version();
+        // Only the changes count
+        assert!(article_map.set_read(&key, false).is_none());
+        assert_eq!(article_map.version(), version);
+        article_map.set_starred(&key, true);
+        assert!(article_map.version() > version);
+        let version = article_map.version();
+        article_map.update_source("source", &[]);
+        assert!(article_map.version() > version);
+        let version = article_map.version();
+        article_map.remove(&key);
+        assert!(article_map.version() > version);
+        let version = article_map.version();
+        assert!(article_map.remove(&key).is_none());
+        assert_eq!(article_map.version(), version);
+    }
+
+    #[test]
+    fn test_article_map_update_keeps_articles() {
+        let article = Article {
+            id: "1".to_owned(),
+            source: "source".to_owned(),
+            title: "title".to_owned(),
+            sub_title: "sub_title".to_owned(),
+            content: "content".to_owned(),
+            date: None,
+            read: false,
+            starred: false,
+            ..Default::default()
+        };
+        let mut article_2 = article.clone();
+        article_2.id = "2".to_owned();
+        let mut article_map: ArticleMap = Default::default();
+        article_map.insert(article);
+        // The first article is not in the update but it's not removed
+        article_map.update_source("source", &[article_2.clone()]);
+        assert_eq!(article_map.articles().len(), 2);
+        // The articles of the other sources are ignored
+        article_2.source = "other".to_owned();
+        article_map.update_source("source", &[article_2]);
+        assert_eq!(article_map.articles().len(), 2);
+    }
+
+    #[test]
+    fn test_parse_content_hints() {
+        let content = std::fs::read_to_string("tests/feeds/hints.xml").unwrap();
+        let feed = parse_content("source", None, content).unwrap();
+        assert_eq!(feed.articles.len(), 1);
+        assert_eq!(
+            feed.hints,
+            UpdateHints {
+                ttl: Some(60 * 60),
+                skip_hours: vec![0, 1, 23],
+                skip_days: vec![Weekday::Sat, Weekday::Sun],
+                update_period: Some(12 * 60 * 60),
+            }
+        );
+    }
+
+    #[test]
+    fn test_parse_content_err() {
+        assert!(parse_content("source", None, String::from("<html></html>")).is_err());
+        assert!(parse_content("source", None, String::new()).is_err());
+        let json = String::from(r#"{"version": "https://jsonfeed.org/version/1.1"}"#);
+        assert!(parse_content("source", None, json).is_err());
+    }
+
+    #[test]
+    fn test_parse_content_format() {
+        let json = std::fs::read_to_string("tests/feeds/json_feed.json").unwrap();
+        let xml = std::fs::read_to_string("tests/feeds/hints.xml").unwrap();
+        // Detected from the body
+        assert_eq!(
+            parse_content("source", None, json.clone())
+                .unwrap()
+                .articles
+                .len(),
+            2
+        );
+        assert_eq!(
+            parse_content("source", Some("text/plain"), json)
+                .unwrap()
+                .articles
+                .len(),
+            2
+        );
+        assert_eq!(
+            parse_content("source", Some("application/rss+xml"), xml)
+                .unwrap()
+                .articles
+                .len(),
+            1
+        );
+    }
+
+    #[test]
+    fn test_is_json() {
+        assert!(is_json(Some("application/feed+json"), ""));
+        assert!(is_json(Some("Application/JSON; charset=utf-8"), ""));
+        assert!(is_json(None, "  {\"version\": \"\"}"));
+        assert!(!is_json(Some("application/rss+xml"), "<rss></rss>"));
+        assert!(!is_json(None, "<?xml version=\"1.0\"?>"));
+    }
+
     #[test]
     fn test_parse_html_base() {
         let expected = String::from("Hello World!");
@@ -224,6 +1279,33 @@ mod test {
         );
     }
 
+    #[test]
+    fn test_parse_html_unwrapped() {
+        let paragraph = "The plain text is shown when the html can't be rendered. ".repeat(10);
+        let html = format!("<p>{}</p><ul><li>{}</li></ul>", paragraph, paragraph);
+        let text = parse_html(&html);
+        assert_eq!(
+            text,
+            format!("{}\n\n* {}", paragraph.trim_end(), paragraph.trim_end())
+        );
+        let table = parse_html(&format!(
+            "<table><tr><td>{}</td><td>Cell</td></tr></table>",
+            paragraph
+        ));
+        assert!(table
+            .lines()
+            .all(|line| line.chars().count() <= TABLE_WIDTH));
+    }
+
+    #[test]
+    fn test_parse_html_panic() {
+        // html2text panics on a cell spanning zero columns
+        let html =
+            "<table><tr><td colspan=0><a href=\"https://example.com/\">a</a></td></tr></table>";
+        assert_eq!(parse_html(html), html);
+        assert_eq!(parse_links(None, &[html]), Vec::new());
+    }
+
     #[test]
     fn test_parse_html_link() {
         let expected = String::from("Here is a [link][1]\n\n[1] https://example.com");
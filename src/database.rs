@@ -1,16 +1,161 @@
+use chrono::{DateTime, FixedOffset, Utc};
 use sqlx::{sqlite::SqliteConnectOptions, Executor, SqlitePool};
-use std::{path::Path, sync::RwLock};
+use std::{
+    collections::HashMap,
+    convert::TryFrom,
+    path::Path,
+    sync::{Arc, Mutex, RwLock},
+};
+use tokio::time::{interval, Duration};
 
-use crate::content::{Article, ArticleMap};
+use crate::{
+    configuration::{Config, Retention},
+    content::{Article, ArticleMap, Enclosure, FeedMetadata, Link, UpdateHints},
+    update::{ErrorKind, FetchError, FetchStatus},
+};
 
-macro_rules! user_version {
-    () => {
-        1
-    };
+/// Ordered list of the migrations of the database schema. The migration at index `i` upgrades the
+/// database from the version `i` to `i + 1`, where the version 0 is an empty database. New
+/// migrations must only be appended to the list, so the user state in the cache is never lost.
+const MIGRATIONS: &[&[&str]] = &[
+    // 1: Articles cache
+    &["CREATE TABLE IF NOT EXISTS Articles (
+        id TEXT NOT NULL,
+        source TEXT NOT NULL,
+        title TEXT NOT NULL,
+        sub_title TEXT NOT NULL,
+        content TEXT NOT NULL,
+        date DATETIME,
+        PRIMARY KEY (id, source)
+    )"],
+    // 2: Read flag
+    &["ALTER TABLE Articles ADD COLUMN read BOOLEAN NOT NULL DEFAULT 0"],
+    // 3: Starred flag
+    &["ALTER TABLE Articles ADD COLUMN starred BOOLEAN NOT NULL DEFAULT 0"],
+    // 4: Time of insertion for the retention, the default can't be CURRENT_TIMESTAMP when adding a
+    // column so the already present articles are considered inserted now
+    &[
+        "ALTER TABLE Articles ADD COLUMN inserted DATETIME NOT NULL DEFAULT 0",
+        "UPDATE Articles SET inserted = datetime('now')",
+    ],
+    // 5: Full-text index of the articles, kept in sync when inserting and deleting the articles
+    &[
+        "CREATE VIRTUAL TABLE ArticlesSearch USING fts5(
+            id UNINDEXED,
+            source UNINDEXED,
+            title,
+            sub_title,
+            content
+        )",
+        "INSERT INTO ArticlesSearch (id, source, title, sub_title, content)
+            SELECT id, source, title, sub_title, content FROM Articles",
+    ],
+    // 6: State of the sources, the validators of the last response for the conditional requests
+    &["CREATE TABLE IF NOT EXISTS Sources (
+        url TEXT NOT NULL PRIMARY KEY,
+        etag TEXT,
+        last_modified TEXT
+    )"],
+    // 7: Status of the last updates of the sources
+    &[
+        "ALTER TABLE Sources ADD COLUMN last_success DATETIME",
+        "ALTER TABLE Sources ADD COLUMN last_failure DATETIME",
+        "ALTER TABLE Sources ADD COLUMN error_kind TEXT",
+        "ALTER TABLE Sources ADD COLUMN error_message TEXT",
+        "ALTER TABLE Sources ADD COLUMN failures INTEGER NOT NULL DEFAULT 0",
+    ],
+    // 8: Backoff of the failed sources
+    &["ALTER TABLE Sources ADD COLUMN next_retry DATETIME"],
+    // 9: Hints of the publishers on when to request the feeds and the time of the next update
+    &[
+        "ALTER TABLE Sources ADD COLUMN ttl INTEGER",
+        "ALTER TABLE Sources ADD COLUMN skip_hours TEXT NOT NULL DEFAULT ''",
+        "ALTER TABLE Sources ADD COLUMN skip_days TEXT NOT NULL DEFAULT ''",
+        "ALTER TABLE Sources ADD COLUMN update_period INTEGER",
+        "ALTER TABLE Sources ADD COLUMN next_update DATETIME",
+    ],
+    // 10: Link, authors, categories and comments of the articles, the lists are separated by new
+    // lines
+    &[
+        "ALTER TABLE Articles ADD COLUMN link TEXT",
+        "ALTER TABLE Articles ADD COLUMN authors TEXT NOT NULL DEFAULT ''",
+        "ALTER TABLE Articles ADD COLUMN categories TEXT NOT NULL DEFAULT ''",
+        "ALTER TABLE Articles ADD COLUMN comments TEXT",
+    ],
+    // 11: Information on the feeds published in their channel, the validators are removed so the
+    // next update downloads the whole feeds and reads it
+    &[
+        "ALTER TABLE Sources ADD COLUMN title TEXT",
+        "ALTER TABLE Sources ADD COLUMN description TEXT",
+        "ALTER TABLE Sources ADD COLUMN site_link TEXT",
+        "ALTER TABLE Sources ADD COLUMN language TEXT",
+        "ALTER TABLE Sources ADD COLUMN icon TEXT",
+        "ALTER TABLE Sources ADD COLUMN generator TEXT",
+        "UPDATE Sources SET etag = NULL, last_modified = NULL",
+    ],
+    // 12: Media attached to the articles, in the order of the feed
+    &["CREATE TABLE IF NOT EXISTS Enclosures (
+        article_id TEXT NOT NULL,
+        source TEXT NOT NULL,
+        position INTEGER NOT NULL,
+        url TEXT NOT NULL,
+        mime_type TEXT,
+        length INTEGER,
+        duration INTEGER,
+        PRIMARY KEY (article_id, source, position)
+    )"],
+    // 13: Links in the html of the articles, the validators are removed so the articles already
+    // in the cache are parsed again
+    &[
+        "CREATE TABLE IF NOT EXISTS Links (
+            article_id TEXT NOT NULL,
+            source TEXT NOT NULL,
+            position INTEGER NOT NULL,
+            url TEXT NOT NULL,
+            text TEXT NOT NULL,
+            PRIMARY KEY (article_id, source, position)
+        )",
+        "UPDATE Sources SET etag = NULL, last_modified = NULL",
+    ],
+    // 14: Html of the sub title and content, rendered at the width of the view. The validators
+    // are removed so the html of the articles already in the cache is stored.
+    &[
+        "ALTER TABLE Articles ADD COLUMN sub_title_html TEXT NOT NULL DEFAULT ''",
+        "ALTER TABLE Articles ADD COLUMN content_html TEXT NOT NULL DEFAULT ''",
+        "UPDATE Sources SET etag = NULL, last_modified = NULL",
+    ],
+    // 15: Time in microseconds of the last fetch that had the article, the articles still in the
+    // feed are not expired. The articles already in the cache have none until they are fetched.
+    &["ALTER TABLE Articles ADD COLUMN last_seen INTEGER"],
+];
+
+/// Separator of the lists stored in a single column, the names can contain commas
+const LIST_SEPARATOR: &str = "\n";
+
+/// Markers around the matching terms in the snippet of a search result
+pub const SNIPPET_START: &str = "\u{2}";
+pub const SNIPPET_END: &str = "\u{3}";
+
+/// Validators of the last response of a source, sent back to only download a changed feed
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Validators {
+    pub etag: Option<String>,
+    pub last_modified: Option<String>,
+}
+
+/// Article matching a full-text search
+#[derive(Clone, Debug, PartialEq, sqlx::FromRow)]
+pub struct SearchResult {
+    pub id: String,
+    pub source: String,
+    pub title: String,
+    // Part of the article matching the query, the terms are between the snippet markers
+    pub snippet: String,
+    // Lower is better
+    pub rank: f64,
 }
 
-// This will delete and not migrate the database if the version is changed, since is used only as
-// cache
+/// Opens the database and upgrades it to the latest version of the schema
 pub async fn get_database(path: &Path) -> sqlx::Result<SqlitePool> {
     // The pool create asynchronously
     let pool = SqlitePool::connect_lazy_with(
@@ -19,66 +164,127 @@ pub async fn get_database(path: &Path) -> sqlx::Result<SqlitePool> {
             .create_if_missing(true),
     );
 
+    migrate(&pool).await?;
+
+    Ok(pool)
+}
+
+/// Applies the missing migrations one at the time, each one in its own transaction so a failed
+/// migration leaves the database in the previous version
+pub async fn migrate(pool: &SqlitePool) -> sqlx::Result<()> {
     let mut conn = pool.acquire().await?;
-    let version: i32 = sqlx::query_scalar!("PRAGMA user_version;")
+    let user_version = sqlx::query_scalar!("PRAGMA user_version;")
         .fetch_one(&mut conn)
         .await?
-        .unwrap_or(-1);
+        .unwrap_or(0);
+    drop(conn);
 
-    if version != user_version!() {
-        if version != 0 {
-            delete_database(&pool).await?;
+    // A negative version is not written by any build
+    let version = usize::try_from(user_version)
+        .ok()
+        .filter(|version| *version <= MIGRATIONS.len())
+        .ok_or_else(|| {
+            sqlx::Error::Protocol(format!(
+                "database version {} is not supported, the latest version is {}",
+                user_version,
+                MIGRATIONS.len()
+            ))
+        })?;
+
+    for (index, migration) in MIGRATIONS.iter().enumerate().skip(version) {
+        let mut trans = pool.begin().await?;
+        for statement in migration.iter() {
+            trans.execute(*statement).await?;
         }
-        create_database(&pool).await?;
+        // Using execute instead of macro since the query is not a string literal
+        trans
+            .execute(format!("PRAGMA user_version = {};", index + 1).as_str())
+            .await?;
+        trans.commit().await?;
     }
-
-    Ok(pool)
-}
-
-pub async fn create_database(pool: &SqlitePool) -> sqlx::Result<()> {
-    let mut trans = pool.begin().await?;
-    // Using execute instead of macro since the query is not a string literal
-    trans
-        .execute(concat!("PRAGMA user_version = ", user_version!(), ";"))
-        .await?;
-    sqlx::query!(
-        "CREATE TABLE IF NOT EXISTS Articles (
-                id TEXT NOT NULL,
-                source TEXT NOT NULL,
-                title TEXT NOT NULL,
-                sub_title TEXT NOT NULL,
-                content TEXT NOT NULL,
-                date DATETIME,
-                PRIMARY KEY (id, source)
-            )",
-    )
-    .execute(&mut trans)
-    .await?;
-    trans.commit().await
-}
-
-pub async fn delete_database(pool: &SqlitePool) -> sqlx::Result<()> {
-    let mut trans = pool.begin().await?;
-    trans.execute("DROP TABLE IF EXISTS Articles");
-    trans.commit().await?;
     Ok(())
 }
 
 pub async fn get_all(pool: &SqlitePool, content: &RwLock<ArticleMap>) -> sqlx::Result<()> {
     let mut conn = pool.acquire().await?;
-    let articles: Vec<Article> = sqlx::query_as_unchecked!(
-        Article,
-        "SELECT 
+    let rows = sqlx::query!(
+        r#"SELECT
             id,
             source,
             title,
             sub_title,
             content,
-            date
-        FROM Articles",
+            date AS "date: DateTime<FixedOffset>",
+            read,
+            starred,
+            link,
+            authors,
+            categories,
+            comments,
+            sub_title_html,
+            content_html
+        FROM Articles"#,
     )
     .fetch_all(&mut conn)
     .await?;
+    let mut enclosures: HashMap<(String, String), Vec<Enclosure>> = HashMap::new();
+    for row in sqlx::query!(
+        "SELECT article_id, source, url, mime_type, length, duration FROM Enclosures
+        ORDER BY position"
+    )
+    .fetch_all(&mut conn)
+    .await?
+    {
+        enclosures
+            .entry((row.article_id, row.source))
+            .or_default()
+            .push(Enclosure {
+                url: row.url,
+                mime_type: row.mime_type,
+                length: row.length.and_then(|length| u64::try_from(length).ok()),
+                duration: row
+                    .duration
+                    .and_then(|duration| u64::try_from(duration).ok()),
+            });
+    }
+    let mut links: HashMap<(String, String), Vec<Link>> = HashMap::new();
+    for row in sqlx::query!("SELECT article_id, source, url, text FROM Links ORDER BY position")
+        .fetch_all(&mut conn)
+        .await?
+    {
+        links
+            .entry((row.article_id, row.source))
+            .or_default()
+            .push(Link {
+                url: row.url,
+                text: row.text,
+            });
+    }
+    let articles: Vec<Article> = rows
+        .into_iter()
+        .map(|row| Article {
+            enclosures: enclosures
+                .remove(&(row.id.clone(), row.source.clone()))
+                .unwrap_or_default(),
+            links: links
+                .remove(&(row.id.clone(), row.source.clone()))
+                .unwrap_or_default(),
+            id: row.id,
+            source: row.source,
+            title: row.title,
+            sub_title: row.sub_title,
+            content: row.content,
+            date: row.date,
+            read: row.read,
+            starred: row.starred,
+            link: row.link,
+            authors: split_list(&row.authors),
+            categories: split_list(&row.categories),
+            comments: row.comments,
+            sub_title_html: row.sub_title_html,
+            content_html: row.content_html,
+        })
+        .collect();
 
     if !articles.is_empty() {
         let mut content = content.write().unwrap();
@@ -89,18 +295,48 @@ pub async fn get_all(pool: &SqlitePool, content: &RwLock<ArticleMap>) -> sqlx::R
     Ok(())
 }
 
+/// Splits a list stored in a single column
+fn split_list(list: &str) -> Vec<String> {
+    list.split(LIST_SEPARATOR)
+        .filter(|item| !item.is_empty())
+        .map(String::from)
+        .collect()
+}
+
 // TODO: Use this functions in the transaction
 pub async fn _insert_article(pool: &SqlitePool, article: &Article) -> sqlx::Result<i64> {
     let mut conn = pool.acquire().await?;
+    let authors = article.authors.join(LIST_SEPARATOR);
+    let categories = article.categories.join(LIST_SEPARATOR);
     let id = sqlx::query!(
-        "INSERT OR REPLACE INTO Articles (id, source, title, sub_title, content, date)
-            VALUES (?, ?, ?, ?, ?, ?)",
+        "INSERT INTO Articles (
+                id, source, title, sub_title, content, date, link, authors, categories, comments,
+                sub_title_html, content_html, inserted
+            )
+            VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, datetime('now'))
+            ON CONFLICT (id, source) DO UPDATE SET
+                title = excluded.title,
+                sub_title = excluded.sub_title,
+                content = excluded.content,
+                date = excluded.date,
+                link = excluded.link,
+                authors = excluded.authors,
+                categories = excluded.categories,
+                comments = excluded.comments,
+                sub_title_html = excluded.sub_title_html,
+                content_html = excluded.content_html",
         article.id,
         article.source,
         article.title,
         article.sub_title,
         article.content,
         article.date,
+        article.link,
+        authors,
+        categories,
+        article.comments,
+        article.sub_title_html,
+        article.content_html,
     )
     .execute(&mut conn)
     .await?
@@ -108,21 +344,119 @@ pub async fn _insert_article(pool: &SqlitePool, article: &Article) -> sqlx::Resu
     Ok(id)
 }
 
+// The upsert doesn't touch the columns with the user state (e.g. read) so they survive updates
 pub async fn insert_articles(pool: &SqlitePool, articles: &[Article]) -> sqlx::Result<()> {
+    // The same for all the articles, they are the latest content of their source
+    let seen = Utc::now().timestamp_micros();
     let mut trans = pool.begin().await?;
     for article in articles {
+        let authors = article.authors.join(LIST_SEPARATOR);
+        let categories = article.categories.join(LIST_SEPARATOR);
         sqlx::query!(
-            "INSERT OR REPLACE INTO Articles (id, source, title, sub_title, content, date)
-            VALUES (?, ?, ?, ?, ?, ?)",
+            "INSERT INTO Articles (
+                id, source, title, sub_title, content, date, link, authors, categories, comments,
+                sub_title_html, content_html, last_seen, inserted
+            )
+            VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, datetime('now'))
+            ON CONFLICT (id, source) DO UPDATE SET
+                title = excluded.title,
+                sub_title = excluded.sub_title,
+                content = excluded.content,
+                date = excluded.date,
+                link = excluded.link,
+                authors = excluded.authors,
+                categories = excluded.categories,
+                comments = excluded.comments,
+                sub_title_html = excluded.sub_title_html,
+                content_html = excluded.content_html,
+                last_seen = excluded.last_seen",
             article.id,
             article.source,
             article.title,
             article.sub_title,
             article.content,
             article.date,
+            article.link,
+            authors,
+            categories,
+            article.comments,
+            article.sub_title_html,
+            article.content_html,
+            seen,
         )
         .execute(&mut trans)
         .await?;
+        // The full-text index has no unique key, so the old version is removed first
+        sqlx::query!(
+            "DELETE FROM ArticlesSearch WHERE id = ? AND source = ?",
+            article.id,
+            article.source
+        )
+        .execute(&mut trans)
+        .await?;
+        sqlx::query!(
+            "INSERT INTO ArticlesSearch (id, source, title, sub_title, content)
+            VALUES (?, ?, ?, ?, ?)",
+            article.id,
+            article.source,
+            article.title,
+            article.sub_title,
+            article.content,
+        )
+        .execute(&mut trans)
+        .await?;
+        sqlx::query!(
+            "DELETE FROM Enclosures WHERE article_id = ? AND source = ?",
+            article.id,
+            article.source
+        )
+        .execute(&mut trans)
+        .await?;
+        for (position, enclosure) in article.enclosures.iter().enumerate() {
+            let position = position as i64;
+            let length = enclosure
+                .length
+                .and_then(|length| i64::try_from(length).ok());
+            let duration = enclosure
+                .duration
+                .and_then(|duration| i64::try_from(duration).ok());
+            sqlx::query!(
+                "INSERT INTO Enclosures (
+                    article_id, source, position, url, mime_type, length, duration
+                )
+                VALUES (?, ?, ?, ?, ?, ?, ?)",
+                article.id,
+                article.source,
+                position,
+                enclosure.url,
+                enclosure.mime_type,
+                length,
+                duration
+            )
+            .execute(&mut trans)
+            .await?;
+        }
+        sqlx::query!(
+            "DELETE FROM Links WHERE article_id = ? AND source = ?",
+            article.id,
+            article.source
+        )
+        .execute(&mut trans)
+        .await?;
+        for (position, link) in article.links.iter().enumerate() {
+            let position = position as i64;
+            sqlx::query!(
+                "INSERT INTO Links (article_id, source, position, url, text)
+                VALUES (?, ?, ?, ?, ?)",
+                article.id,
+                article.source,
+                position,
+                link.url,
+                link.text
+            )
+            .execute(&mut trans)
+            .await?;
+        }
     }
     trans.commit().await
 }
@@ -131,7 +465,336 @@ pub async fn delete_articles(pool: &SqlitePool, articles: &[(String, String)]) -
     let mut trans = pool.begin().await?;
     for (id, source) in articles {
         sqlx::query!(
-            "DELETE FROM Articles WHERE id = ? AND source = ?",
+            "DELETE FROM Articles WHERE id = ? AND source = ? AND starred = 0",
+            id,
+            source
+        )
+        .execute(&mut trans)
+        .await?;
+        // Starred articles are kept, so they stay in the index too
+        sqlx::query!(
+            "DELETE FROM ArticlesSearch WHERE id = ? AND source = ? AND NOT EXISTS (
+                SELECT 1 FROM Articles WHERE id = ? AND source = ?
+            )",
+            id,
+            source,
+            id,
+            source
+        )
+        .execute(&mut trans)
+        .await?;
+        sqlx::query!(
+            "DELETE FROM Enclosures WHERE article_id = ? AND source = ? AND NOT EXISTS (
+                SELECT 1 FROM Articles WHERE id = ? AND source = ?
+            )",
+            id,
+            source,
+            id,
+            source
+        )
+        .execute(&mut trans)
+        .await?;
+        sqlx::query!(
+            "DELETE FROM Links WHERE article_id = ? AND source = ? AND NOT EXISTS (
+                SELECT 1 FROM Articles WHERE id = ? AND source = ?
+            )",
+            id,
+            source,
+            id,
+            source
+        )
+        .execute(&mut trans)
+        .await?;
+    }
+    trans.commit().await
+}
+
+/// Returns the validators of the last response of the source
+pub async fn get_validators(pool: &SqlitePool, url: &str) -> sqlx::Result<Validators> {
+    let mut conn = pool.acquire().await?;
+    let validators = sqlx::query_as!(
+        Validators,
+        "SELECT etag, last_modified FROM Sources WHERE url = ?",
+        url
+    )
+    .fetch_optional(&mut conn)
+    .await?;
+    Ok(validators.unwrap_or_default())
+}
+
+/// Stores the validators of the last response of the sources
+pub async fn set_validators(
+    pool: &SqlitePool,
+    validators: &[(String, Validators)],
+) -> sqlx::Result<()> {
+    let mut trans = pool.begin().await?;
+    for (url, validators) in validators {
+        sqlx::query!(
+            "INSERT INTO Sources (url, etag, last_modified) VALUES (?, ?, ?)
+            ON CONFLICT (url) DO UPDATE SET
+                etag = excluded.etag,
+                last_modified = excluded.last_modified",
+            url,
+            validators.etag,
+            validators.last_modified
+        )
+        .execute(&mut trans)
+        .await?;
+    }
+    trans.commit().await
+}
+
+/// Returns the hints of the publisher of the source on when to request the feed
+pub async fn get_update_hints(pool: &SqlitePool, url: &str) -> sqlx::Result<UpdateHints> {
+    let mut conn = pool.acquire().await?;
+    let row = sqlx::query!(
+        "SELECT ttl, skip_hours, skip_days, update_period FROM Sources WHERE url = ?",
+        url
+    )
+    .fetch_optional(&mut conn)
+    .await?;
+    // The lists are stored as comma separated values
+    Ok(row
+        .map(|row| UpdateHints {
+            ttl: row.ttl.and_then(|ttl| u64::try_from(ttl).ok()),
+            skip_hours: row
+                .skip_hours
+                .split(',')
+                .filter_map(|hour| hour.parse().ok())
+                .collect(),
+            skip_days: row
+                .skip_days
+                .split(',')
+                .filter_map(|day| day.parse().ok())
+                .collect(),
+            update_period: row
+                .update_period
+                .and_then(|period| u64::try_from(period).ok()),
+        })
+        .unwrap_or_default())
+}
+
+/// Stores the hints of the publishers of the sources
+pub async fn set_update_hints(
+    pool: &SqlitePool,
+    hints: &[(String, UpdateHints)],
+) -> sqlx::Result<()> {
+    let mut trans = pool.begin().await?;
+    for (url, hints) in hints {
+        let ttl = hints.ttl.and_then(|ttl| i64::try_from(ttl).ok());
+        let skip_hours: Vec<String> = hints.skip_hours.iter().map(u32::to_string).collect();
+        let skip_hours = skip_hours.join(",");
+        let skip_days: Vec<String> = hints.skip_days.iter().map(|day| day.to_string()).collect();
+        let skip_days = skip_days.join(",");
+        let update_period = hints
+            .update_period
+            .and_then(|period| i64::try_from(period).ok());
+        sqlx::query!(
+            "INSERT INTO Sources (url, ttl, skip_hours, skip_days, update_period)
+            VALUES (?, ?, ?, ?, ?)
+            ON CONFLICT (url) DO UPDATE SET
+                ttl = excluded.ttl,
+                skip_hours = excluded.skip_hours,
+                skip_days = excluded.skip_days,
+                update_period = excluded.update_period",
+            url,
+            ttl,
+            skip_hours,
+            skip_days,
+            update_period
+        )
+        .execute(&mut trans)
+        .await?;
+    }
+    trans.commit().await
+}
+
+/// Returns the information on the feeds by url
+pub async fn get_feed_metadata(pool: &SqlitePool) -> sqlx::Result<HashMap<String, FeedMetadata>> {
+    let mut conn = pool.acquire().await?;
+    let rows = sqlx::query!(
+        "SELECT url, title, description, site_link, language, icon, generator FROM Sources"
+    )
+    .fetch_all(&mut conn)
+    .await?;
+    Ok(rows
+        .into_iter()
+        .map(|row| {
+            let metadata = FeedMetadata {
+                title: row.title,
+                description: row.description,
+                site_link: row.site_link,
+                language: row.language,
+                icon: row.icon,
+                generator: row.generator,
+            };
+            (row.url, metadata)
+        })
+        .collect())
+}
+
+/// Stores the information on the feeds published in their last version
+pub async fn set_feed_metadata(
+    pool: &SqlitePool,
+    metadata: &[(String, FeedMetadata)],
+) -> sqlx::Result<()> {
+    let mut trans = pool.begin().await?;
+    for (url, metadata) in metadata {
+        sqlx::query!(
+            "INSERT INTO Sources (url, title, description, site_link, language, icon, generator)
+            VALUES (?, ?, ?, ?, ?, ?, ?)
+            ON CONFLICT (url) DO UPDATE SET
+                title = excluded.title,
+                description = excluded.description,
+                site_link = excluded.site_link,
+                language = excluded.language,
+                icon = excluded.icon,
+                generator = excluded.generator",
+            url,
+            metadata.title,
+            metadata.description,
+            metadata.site_link,
+            metadata.language,
+            metadata.icon,
+            metadata.generator
+        )
+        .execute(&mut trans)
+        .await?;
+    }
+    trans.commit().await
+}
+
+/// Returns the status of the updates of the sources by url
+pub async fn get_fetch_status(pool: &SqlitePool) -> sqlx::Result<HashMap<String, FetchStatus>> {
+    let mut conn = pool.acquire().await?;
+    let rows = sqlx::query!(
+        r#"SELECT
+            url,
+            last_success AS "last_success: DateTime<Utc>",
+            last_failure AS "last_failure: DateTime<Utc>",
+            error_kind,
+            error_message,
+            failures,
+            next_retry AS "next_retry: DateTime<Utc>",
+            next_update AS "next_update: DateTime<Utc>"
+        FROM Sources"#
+    )
+    .fetch_all(&mut conn)
+    .await?;
+    Ok(rows
+        .into_iter()
+        .map(|row| {
+            let message = row.error_message.unwrap_or_default();
+            let error = row.error_kind.map(|kind| FetchError {
+                kind: ErrorKind::from_code(&kind),
+                message,
+                retry_after: None,
+            });
+            let status = FetchStatus {
+                last_success: row.last_success,
+                last_failure: row.last_failure,
+                error,
+                failures: u32::try_from(row.failures).unwrap_or(0),
+                next_retry: row.next_retry,
+                next_update: row.next_update,
+            };
+            (row.url, status)
+        })
+        .collect())
+}
+
+/// Stores the status of the updates of the sources
+pub async fn set_fetch_status(
+    pool: &SqlitePool,
+    status: &[(String, FetchStatus)],
+) -> sqlx::Result<()> {
+    let mut trans = pool.begin().await?;
+    for (url, status) in status {
+        let error_kind = status.error.as_ref().map(|error| error.kind.code());
+        let error_message = status.error.as_ref().map(|error| error.message.as_str());
+        sqlx::query!(
+            "INSERT INTO Sources (
+                url,
+                last_success,
+                last_failure,
+                error_kind,
+                error_message,
+                failures,
+                next_retry,
+                next_update
+            )
+            VALUES (?, ?, ?, ?, ?, ?, ?, ?)
+            ON CONFLICT (url) DO UPDATE SET
+                last_success = excluded.last_success,
+                last_failure = excluded.last_failure,
+                error_kind = excluded.error_kind,
+                error_message = excluded.error_message,
+                failures = excluded.failures,
+                next_retry = excluded.next_retry,
+                next_update = excluded.next_update",
+            url,
+            status.last_success,
+            status.last_failure,
+            error_kind,
+            error_message,
+            status.failures,
+            status.next_retry,
+            status.next_update,
+        )
+        .execute(&mut trans)
+        .await?;
+    }
+    trans.commit().await
+}
+
+/// Searches the query in the title, sub title and content of all the articles in the cache. The
+/// results are ordered by relevance, with the matches in the title weighting more.
+pub async fn search(pool: &SqlitePool, query: &str, limit: i64) -> sqlx::Result<Vec<SearchResult>> {
+    let query = match_query(query);
+    if query.is_empty() {
+        return Ok(Vec::new());
+    }
+    let mut conn = pool.acquire().await?;
+    sqlx::query_as(
+        "SELECT
+            id,
+            source,
+            title,
+            snippet(ArticlesSearch, -1, ?, ?, '...', 16) AS snippet,
+            bm25(ArticlesSearch, 0.0, 0.0, 10.0, 5.0, 1.0) AS rank
+        FROM ArticlesSearch
+        WHERE ArticlesSearch MATCH ?
+        ORDER BY rank
+        LIMIT ?",
+    )
+    .bind(SNIPPET_START)
+    .bind(SNIPPET_END)
+    .bind(query)
+    .bind(limit)
+    .fetch_all(&mut conn)
+    .await
+}
+
+/// Converts the words of the user into a full-text query matching all of them. Every word is
+/// quoted so the characters with a meaning in the query syntax are searched as they are.
+fn match_query(query: &str) -> String {
+    query
+        .split_whitespace()
+        .map(|word| format!("\"{}\"", word.replace('"', "\"\"")))
+        .collect::<Vec<String>>()
+        .join(" ")
+}
+
+pub async fn set_read(
+    pool: &SqlitePool,
+    articles: &[(String, String)],
+    read: bool,
+) -> sqlx::Result<()> {
+    let mut trans = pool.begin().await?;
+    for (id, source) in articles {
+        sqlx::query!(
+            "UPDATE Articles SET read = ? WHERE id = ? AND source = ?",
+            read,
             id,
             source
         )
@@ -140,3 +803,584 @@ pub async fn delete_articles(pool: &SqlitePool, articles: &[(String, String)]) -
     }
     trans.commit().await
 }
+
+pub async fn set_starred(
+    pool: &SqlitePool,
+    (id, source): &(String, String),
+    starred: bool,
+) -> sqlx::Result<()> {
+    let mut conn = pool.acquire().await?;
+    sqlx::query!(
+        "UPDATE Articles SET starred = ? WHERE id = ? AND source = ?",
+        starred,
+        id,
+        source
+    )
+    .execute(&mut conn)
+    .await?;
+    Ok(())
+}
+
+/// Deletes the articles expired by the retention policy and returns their keys. The starred
+/// articles are never deleted. If an article has no date, the time it was inserted is used.
+///
+/// The articles in the last fetched content of their source are kept, otherwise the next update
+/// would insert them again as unread. They expire once they are no longer in the feed.
+///
+/// The dates are compared as julian days since the text of a date can be in UTC or have an
+/// offset, like the RFC 3339 dates of the feeds, and the insert times are in another format.
+pub async fn delete_expired(
+    pool: &SqlitePool,
+    retention: &Retention,
+) -> sqlx::Result<Vec<(String, String)>> {
+    let mut conn = pool.acquire().await?;
+    let expired: Vec<(String, String)> = match retention {
+        Retention::Forever => Vec::new(),
+        Retention::Days(days) => {
+            let modifier = format!("-{} days", days);
+            sqlx::query!(
+                "SELECT id, source FROM Articles
+                WHERE starred = 0 AND julianday(COALESCE(date, inserted)) < julianday('now', ?)
+                    AND (last_seen IS NULL OR last_seen < (
+                        SELECT MAX(last_seen) FROM Articles AS latest
+                        WHERE latest.source = Articles.source
+                    ))",
+                modifier
+            )
+            .fetch_all(&mut conn)
+            .await?
+            .into_iter()
+            .map(|row| (row.id, row.source))
+            .collect()
+        }
+        Retention::PerSource(count) => {
+            let count = *count as i64;
+            sqlx::query!(
+                "SELECT id, source FROM (
+                    SELECT id, source, starred, last_seen, ROW_NUMBER() OVER (
+                        PARTITION BY source ORDER BY julianday(COALESCE(date, inserted)) DESC
+                    ) AS position, MAX(last_seen) OVER (PARTITION BY source) AS latest
+                    FROM Articles
+                )
+                WHERE starred = 0 AND position > ?
+                    AND (last_seen IS NULL OR last_seen < latest)",
+                count
+            )
+            .fetch_all(&mut conn)
+            .await?
+            .into_iter()
+            .map(|row| (row.id, row.source))
+            .collect()
+        }
+    };
+    drop(conn);
+    delete_articles(pool, &expired).await?;
+    Ok(expired)
+}
+
+/// Periodically removes the expired articles from the cache and from the content. A failed
+/// cleanup is reported in the errors and tried again at the next one.
+pub fn cleanup_thread(
+    config: &Config,
+    pool: &Arc<SqlitePool>,
+    content: &Arc<RwLock<ArticleMap>>,
+    errors: &Arc<Mutex<Vec<String>>>,
+) {
+    if config.retention != Retention::Forever {
+        let retention = config.retention;
+        let pool = Arc::clone(pool);
+        let content = Arc::clone(content);
+        let errors = Arc::clone(errors);
+
+        tokio::spawn(async move {
+            // Once every hour is enough since the retention is at least one day or one fetch
+            let mut interval = interval(Duration::from_secs(60 * 60));
+            loop {
+                interval.tick().await;
+                let expired = match delete_expired(&pool, &retention).await {
+                    Ok(expired) => expired,
+                    Err(err) => {
+                        errors
+                            .lock()
+                            .unwrap()
+                            .push(format!("Failed to delete the expired articles: {}", err));
+                        continue;
+                    }
+                };
+                if !expired.is_empty() {
+                    let mut content = content.write().unwrap();
+                    for key in &expired {
+                        content.remove(key);
+                    }
+                }
+            }
+        });
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use chrono::Weekday;
+
+    /// Creates a new empty database in the temporary directory
+    async fn test_database(name: &str) -> SqlitePool {
+        let path = std::env::temp_dir().join(format!("feedrs_{}.db", name));
+        if path.exists() {
+            std::fs::remove_file(&path).unwrap();
+        }
+        get_database(&path).await.unwrap()
+    }
+
+    async fn user_version(pool: &SqlitePool) -> i64 {
+        sqlx::query_scalar("PRAGMA user_version;")
+            .fetch_one(pool)
+            .await
+            .unwrap()
+    }
+
+    /// Returns the name, type, not null and default value of the columns of a table
+    async fn columns(
+        pool: &SqlitePool,
+        table: &str,
+    ) -> Vec<(String, String, bool, Option<String>)> {
+        sqlx::query_as(&format!(
+            "SELECT name, type, \"notnull\", dflt_value FROM pragma_table_info('{}')",
+            table
+        ))
+        .fetch_all(pool)
+        .await
+        .unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_migrate_new() {
+        let pool = test_database("migrate_new").await;
+        assert_eq!(user_version(&pool).await, MIGRATIONS.len() as i64);
+        // Migrating again doesn't change anything
+        migrate(&pool).await.unwrap();
+        assert_eq!(user_version(&pool).await, MIGRATIONS.len() as i64);
+    }
+
+    #[tokio::test]
+    async fn test_migrate_unsupported() {
+        let pool = test_database("migrate_unsupported").await;
+        for version in [-1, MIGRATIONS.len() as i64 + 1].iter() {
+            pool.execute(format!("PRAGMA user_version = {};", version).as_str())
+                .await
+                .unwrap();
+            assert!(migrate(&pool).await.is_err());
+            // The database is left as it is
+            assert_eq!(user_version(&pool).await, *version);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_migrate_v1() {
+        let path = std::env::temp_dir().join("feedrs_migrate_v1.db");
+        if path.exists() {
+            std::fs::remove_file(&path).unwrap();
+        }
+        // Create the database with the fixture of the first version
+        {
+            let pool = SqlitePool::connect_lazy_with(
+                SqliteConnectOptions::new()
+                    .filename(&path)
+                    .create_if_missing(true),
+            );
+            pool.execute(include_str!("../tests/database/v1.sql"))
+                .await
+                .unwrap();
+            assert_eq!(user_version(&pool).await, 1);
+            pool.close().await;
+        }
+        let pool = get_database(&path).await.unwrap();
+        assert_eq!(user_version(&pool).await, MIGRATIONS.len() as i64);
+        // Same schema of a new database
+        let new_pool = test_database("migrate_v1_new").await;
+        assert_eq!(
+            columns(&pool, "Articles").await,
+            columns(&new_pool, "Articles").await
+        );
+        // All the articles are still there with the default user state
+        let content = RwLock::new(ArticleMap::default());
+        get_all(&pool, &content).await.unwrap();
+        let articles: Vec<Article> = content
+            .read()
+            .unwrap()
+            .articles()
+            .iter()
+            .map(|x| (**x).clone())
+            .collect();
+        assert_eq!(articles.len(), 2);
+        // The articles without a date are the first ones
+        assert_eq!(articles[0].id, "id_2");
+        assert_eq!(articles[0].date, None);
+        assert_eq!(articles[1].id, "id_1");
+        assert_eq!(articles[1].title, "title_1");
+        assert_eq!(articles[1].content, "content_1");
+        assert_eq!(
+            articles[1].date,
+            DateTime::parse_from_rfc3339("2016-11-08T08:50:23+00:00").ok()
+        );
+        assert!(articles.iter().all(|x| !x.read && !x.starred));
+        // The articles already in the cache are indexed
+        assert_eq!(search(&pool, "content_1", 10).await.unwrap().len(), 1);
+        // The inserted time is set for the retention, so only the dated article is expired
+        assert_eq!(
+            delete_expired(&pool, &Retention::Days(1)).await.unwrap(),
+            vec![("id_1".to_owned(), "source".to_owned())]
+        );
+    }
+
+    #[test]
+    fn test_match_query() {
+        assert_eq!(match_query("rust  async"), "\"rust\" \"async\"");
+        assert_eq!(
+            match_query("say \"hi\" OR *"),
+            "\"say\" \"\"\"hi\"\"\" \"OR\" \"*\""
+        );
+        assert_eq!(match_query("  "), "");
+    }
+
+    #[tokio::test]
+    async fn test_search() {
+        let pool = test_database("search").await;
+        let mut articles = vec![
+            article("1", "source", "2000-01-01T00:00:00+00:00"),
+            article("2", "source", "2000-01-01T00:00:00+00:00"),
+            article("3", "source", "2000-01-01T00:00:00+00:00"),
+        ];
+        articles[0].content = "Writing a feed reader in Rust".to_owned();
+        articles[1].title = "Rust 1.50".to_owned();
+        articles[2].content = "Nothing to see".to_owned();
+        insert_articles(&pool, &articles).await.unwrap();
+        // The match in the title is the first one
+        let results = search(&pool, "rust", 10).await.unwrap();
+        let ids: Vec<&str> = results.iter().map(|x| x.id.as_str()).collect();
+        assert_eq!(ids, vec!["2", "1"]);
+        assert_eq!(
+            results[1].snippet,
+            format!(
+                "Writing a feed reader in {}Rust{}",
+                SNIPPET_START, SNIPPET_END
+            )
+        );
+        // All the words must match, the special characters are ignored
+        let results = search(&pool, "feed (rust", 10).await.unwrap();
+        assert_eq!(results.len(), 1);
+        assert!(search(&pool, "", 10).await.unwrap().is_empty());
+        // Updated articles replace the indexed version
+        articles[0].content = "Writing a feed reader".to_owned();
+        insert_articles(&pool, &articles[..1]).await.unwrap();
+        assert_eq!(search(&pool, "rust", 10).await.unwrap().len(), 1);
+        // Deleted articles are removed from the index, except the starred ones
+        set_starred(&pool, &("2".to_owned(), "source".to_owned()), true)
+            .await
+            .unwrap();
+        let keys = vec![
+            ("1".to_owned(), "source".to_owned()),
+            ("2".to_owned(), "source".to_owned()),
+        ];
+        delete_articles(&pool, &keys).await.unwrap();
+        assert_eq!(search(&pool, "rust", 10).await.unwrap().len(), 1);
+        assert!(search(&pool, "feed", 10).await.unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_validators() {
+        let pool = test_database("validators").await;
+        let url = "https://example.com/feed.xml";
+        assert_eq!(
+            get_validators(&pool, url).await.unwrap(),
+            Validators::default()
+        );
+        let validators = Validators {
+            etag: Some("\"v1\"".to_owned()),
+            last_modified: Some("Wed, 21 Oct 2015 07:28:00 GMT".to_owned()),
+        };
+        set_validators(&pool, &[(url.to_owned(), validators.clone())])
+            .await
+            .unwrap();
+        assert_eq!(get_validators(&pool, url).await.unwrap(), validators);
+        // A response without validators clears the old ones
+        set_validators(&pool, &[(url.to_owned(), Validators::default())])
+            .await
+            .unwrap();
+        assert_eq!(
+            get_validators(&pool, url).await.unwrap(),
+            Validators::default()
+        );
+    }
+
+    #[tokio::test]
+    async fn test_fetch_status() {
+        let pool = test_database("fetch_status").await;
+        let url = "https://example.com/feed.xml";
+        let validators = Validators {
+            etag: Some("\"v1\"".to_owned()),
+            last_modified: None,
+        };
+        set_validators(&pool, &[(url.to_owned(), validators.clone())])
+            .await
+            .unwrap();
+        let mut status = FetchStatus::default();
+        // Whole seconds since the dates are stored without the fraction
+        let time = DateTime::parse_from_rfc3339("2021-01-01T10:00:00+00:00")
+            .unwrap()
+            .with_timezone(&Utc);
+        status.succeeded(time, time);
+        status.failed(
+            FetchError {
+                kind: ErrorKind::Http(404),
+                message: "Not Found".to_owned(),
+                retry_after: None,
+            },
+            time,
+        );
+        set_fetch_status(&pool, &[(url.to_owned(), status.clone())])
+            .await
+            .unwrap();
+        let result = get_fetch_status(&pool).await.unwrap();
+        assert_eq!(result.len(), 1);
+        assert_eq!(result[url], status);
+        // The status doesn't change the validators
+        assert_eq!(get_validators(&pool, url).await.unwrap(), validators);
+    }
+
+    #[tokio::test]
+    async fn test_update_hints() {
+        let pool = test_database("update_hints").await;
+        let url = "https://example.com/feed.xml";
+        assert_eq!(
+            get_update_hints(&pool, url).await.unwrap(),
+            UpdateHints::default()
+        );
+        let hints = UpdateHints {
+            ttl: Some(60 * 60),
+            skip_hours: vec![0, 23],
+            skip_days: vec![Weekday::Sat, Weekday::Sun],
+            update_period: Some(24 * 60 * 60),
+        };
+        set_update_hints(&pool, &[(url.to_owned(), hints.clone())])
+            .await
+            .unwrap();
+        assert_eq!(get_update_hints(&pool, url).await.unwrap(), hints);
+        set_update_hints(&pool, &[(url.to_owned(), UpdateHints::default())])
+            .await
+            .unwrap();
+        assert_eq!(
+            get_update_hints(&pool, url).await.unwrap(),
+            UpdateHints::default()
+        );
+    }
+
+    #[tokio::test]
+    async fn test_feed_metadata() {
+        let pool = test_database("feed_metadata").await;
+        assert!(get_feed_metadata(&pool).await.unwrap().is_empty());
+        let url = "https://example.com/feed.xml".to_owned();
+        let metadata = FeedMetadata {
+            title: Some("Title".to_owned()),
+            description: Some("Description".to_owned()),
+            site_link: Some("https://example.com/".to_owned()),
+            language: Some("en".to_owned()),
+            icon: Some("https://example.com/icon.png".to_owned()),
+            generator: None,
+        };
+        set_feed_metadata(&pool, &[(url.clone(), metadata.clone())])
+            .await
+            .unwrap();
+        // The other columns of the source are kept
+        set_validators(
+            &pool,
+            &[(
+                url.clone(),
+                Validators {
+                    etag: Some("\"v1\"".to_owned()),
+                    last_modified: None,
+                },
+            )],
+        )
+        .await
+        .unwrap();
+        let mut expected = HashMap::new();
+        expected.insert(url, metadata);
+        assert_eq!(get_feed_metadata(&pool).await.unwrap(), expected);
+    }
+
+    fn article(id: &str, source: &str, date: &str) -> Article {
+        Article {
+            id: id.to_owned(),
+            source: source.to_owned(),
+            title: "title".to_owned(),
+            sub_title: "sub_title".to_owned(),
+            content: "content".to_owned(),
+            date: DateTime::parse_from_rfc3339(date).ok(),
+            read: false,
+            starred: false,
+            ..Default::default()
+        }
+    }
+
+    #[tokio::test]
+    async fn test_insert_articles_metadata() {
+        let pool = test_database("insert_articles_metadata").await;
+        let mut article = article("1", "source", "2021-03-01T08:00:00+01:00");
+        article.link = Some("https://example.com/1".to_owned());
+        article.authors = vec!["Doe, Jane".to_owned(), "John Doe".to_owned()];
+        article.categories = vec!["rust".to_owned()];
+        article.comments = Some("https://example.com/1#comments".to_owned());
+        article.sub_title_html = "<p>Sub title</p>".to_owned();
+        article.content_html = "<p>Content</p>".to_owned();
+        article.enclosures = vec![
+            Enclosure {
+                url: "https://example.com/1.mp3".to_owned(),
+                mime_type: Some("audio/mpeg".to_owned()),
+                length: Some(1024),
+                duration: Some(60),
+            },
+            Enclosure {
+                url: "https://example.com/1.ogg".to_owned(),
+                ..Default::default()
+            },
+        ];
+        insert_articles(&pool, &[article.clone()]).await.unwrap();
+        article.links = vec![Link {
+            url: "https://example.com/about".to_owned(),
+            text: "About".to_owned(),
+        }];
+        // Updating the article replaces the enclosures and links
+        insert_articles(&pool, &[article.clone()]).await.unwrap();
+        let content = RwLock::new(ArticleMap::default());
+        get_all(&pool, &content).await.unwrap();
+        let articles = content.read().unwrap().articles().clone();
+        assert_eq!(articles.len(), 1);
+        assert_eq!(**articles.iter().next().unwrap(), article);
+    }
+
+    #[tokio::test]
+    async fn test_delete_expired_days() {
+        let pool = test_database("delete_expired_days").await;
+        let articles = vec![
+            article("1", "source", "2000-01-01T00:00:00+00:00"),
+            article("2", "source", "2000-01-01T00:00:00+00:00"),
+            article("3", "source", &Utc::now().to_rfc3339()),
+        ];
+        insert_articles(&pool, &articles).await.unwrap();
+        set_starred(&pool, &("2".to_owned(), "source".to_owned()), true)
+            .await
+            .unwrap();
+        // The articles are still in the feed
+        assert!(delete_expired(&pool, &Retention::Days(30))
+            .await
+            .unwrap()
+            .is_empty());
+        insert_articles(&pool, &articles[2..]).await.unwrap();
+        let expired = delete_expired(&pool, &Retention::Days(30)).await.unwrap();
+        assert_eq!(expired, vec![("1".to_owned(), "source".to_owned())]);
+        assert!(delete_expired(&pool, &Retention::Days(30))
+            .await
+            .unwrap()
+            .is_empty());
+    }
+
+    /// Inserts an article with the date stored as written in the feed instead of in UTC
+    async fn insert_with_date(pool: &SqlitePool, id: &str, source: &str, date: &str) {
+        insert_articles(pool, &[article(id, source, date)])
+            .await
+            .unwrap();
+        sqlx::query("UPDATE Articles SET date = ? WHERE id = ? AND source = ?")
+            .bind(date)
+            .bind(id)
+            .bind(source)
+            .execute(pool)
+            .await
+            .unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_delete_expired_offset() {
+        let pool = test_database("delete_expired_offset").await;
+        let cutoff = Utc::now() - chrono::Duration::days(1);
+        let east = FixedOffset::east_opt(5 * 3600).unwrap();
+        let west = FixedOffset::west_opt(5 * 3600).unwrap();
+        // Written later and earlier than the cutoff in their zone
+        let expired = (cutoff - chrono::Duration::hours(1)).with_timezone(&east);
+        let kept = (cutoff + chrono::Duration::hours(1)).with_timezone(&west);
+        insert_with_date(&pool, "1", "source", &expired.to_rfc3339()).await;
+        insert_with_date(&pool, "2", "source", &kept.to_rfc3339()).await;
+        let expired = delete_expired(&pool, &Retention::Days(1)).await.unwrap();
+        assert_eq!(expired, vec![("1".to_owned(), "source".to_owned())]);
+        // The newest article is written earlier than the others in its zone
+        insert_with_date(&pool, "1", "source_2", "2000-01-01T10:00:00+05:00").await;
+        insert_with_date(&pool, "2", "source_2", "2000-01-01T08:00:00+00:00").await;
+        insert_with_date(&pool, "3", "source_2", "2000-01-01T07:00:00+00:00").await;
+        let expired = delete_expired(&pool, &Retention::PerSource(2))
+            .await
+            .unwrap();
+        assert_eq!(expired, vec![("1".to_owned(), "source_2".to_owned())]);
+    }
+
+    #[tokio::test]
+    async fn test_delete_expired_per_source() {
+        let pool = test_database("delete_expired_per_source").await;
+        let articles = vec![
+            article("1", "source_1", "2000-01-01T00:00:00+00:00"),
+            article("2", "source_1", "2000-01-02T00:00:00+00:00"),
+            article("3", "source_1", "2000-01-03T00:00:00+00:00"),
+            article("1", "source_2", "2000-01-01T00:00:00+00:00"),
+        ];
+        insert_articles(&pool, &articles).await.unwrap();
+        // The next fetch no longer has the oldest article
+        insert_articles(&pool, &articles[1..3]).await.unwrap();
+        let mut expired = delete_expired(&pool, &Retention::PerSource(2))
+            .await
+            .unwrap();
+        expired.sort();
+        assert_eq!(expired, vec![("1".to_owned(), "source_1".to_owned())]);
+        assert!(delete_expired(&pool, &Retention::Forever)
+            .await
+            .unwrap()
+            .is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_delete_expired_fetched() {
+        let pool = test_database("delete_expired_fetched").await;
+        let mut articles = vec![
+            article("1", "source", "2000-01-01T00:00:00+00:00"),
+            article("2", "source", "2000-01-02T00:00:00+00:00"),
+            article("3", "source", "2000-01-03T00:00:00+00:00"),
+        ];
+        articles[0].date = None;
+        insert_articles(&pool, &articles).await.unwrap();
+        let keys: Vec<(String, String)> = articles
+            .iter()
+            .map(|article| (article.id.clone(), article.source.clone()))
+            .collect();
+        set_read(&pool, &keys, true).await.unwrap();
+        // The undated article was fetched long ago
+        sqlx::query("UPDATE Articles SET inserted = '2000-01-01 00:00:00' WHERE id = '1'")
+            .execute(&pool)
+            .await
+            .unwrap();
+        // The articles still in the feed are kept, so the next fetch doesn't make them unread
+        for retention in [Retention::Days(1), Retention::PerSource(1)].iter() {
+            assert!(delete_expired(&pool, retention).await.unwrap().is_empty());
+            insert_articles(&pool, &articles).await.unwrap();
+            let read: Vec<bool> = sqlx::query_scalar("SELECT read FROM Articles ORDER BY id")
+                .fetch_all(&pool)
+                .await
+                .unwrap();
+            assert_eq!(read, vec![true, true, true]);
+        }
+        // They expire once they are no longer in the feed
+        insert_articles(&pool, &articles[2..]).await.unwrap();
+        let mut expired = delete_expired(&pool, &Retention::PerSource(1))
+            .await
+            .unwrap();
+        expired.sort();
+        assert_eq!(expired, keys[..2].to_vec());
+    }
+}
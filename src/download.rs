@@ -0,0 +1,397 @@
+use reqwest::{Client, Url};
+use std::{
+    io,
+    path::{Path, PathBuf},
+    sync::{Arc, RwLock},
+    time::{Duration, Instant},
+};
+use tokio::{fs, io::AsyncWriteExt};
+
+/// How long a finished download is shown in the status line
+pub const SHOW_FINISHED: Duration = Duration::from_secs(10);
+
+/// State of the download of an enclosure
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum DownloadState {
+    Running,
+    // Path of the downloaded file
+    Done(PathBuf),
+    Failed(String),
+}
+
+/// Progress of the download of an enclosure
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Download {
+    pub url: String,
+    // Bytes written in the file
+    pub received: u64,
+    // Size of the file, if sent by the server
+    pub total: Option<u64>,
+    pub state: DownloadState,
+    // Time the download finished or failed
+    pub finished: Option<Instant>,
+}
+
+impl Download {
+    /// Name of the file being downloaded
+    pub fn file_name(&self) -> String {
+        file_name(&self.url)
+    }
+}
+
+/// Downloads the enclosure in the directory in its own task. The progress is at the end of the
+/// downloads, the same url is not downloaded twice at the same time.
+pub fn download(client: &Client, url: &str, dir: &Path, downloads: &Arc<RwLock<Vec<Download>>>) {
+    {
+        let mut downloads = downloads.write().unwrap();
+        if downloads
+            .iter()
+            .any(|download| download.url == url && download.state == DownloadState::Running)
+        {
+            return;
+        }
+        downloads.push(Download {
+            url: String::from(url),
+            received: 0,
+            total: None,
+            state: DownloadState::Running,
+            finished: None,
+        });
+    }
+    let client = client.clone();
+    let url = String::from(url);
+    let dir = dir.to_path_buf();
+    let downloads = Arc::clone(downloads);
+    tokio::spawn(async move {
+        let result = download_file(&client, &url, &dir, &downloads).await;
+        update_progress(&downloads, &url, |download| {
+            download.state = match result {
+                Ok(path) => DownloadState::Done(path),
+                Err(err) => DownloadState::Failed(err.to_string()),
+            };
+            download.finished = Some(Instant::now());
+        });
+    });
+}
+
+/// Writes the file in a temporary file next to the destination, so an interrupted download is
+/// never mistaken for a complete one. The temporary file of a failed download is removed, so it
+/// doesn't keep its name reserved.
+async fn download_file(
+    client: &Client,
+    url: &str,
+    dir: &Path,
+    downloads: &RwLock<Vec<Download>>,
+) -> anyhow::Result<PathBuf> {
+    let mut response = client.get(url).send().await?.error_for_status()?;
+    let total = response.content_length();
+    update_progress(downloads, url, |download| download.total = total);
+    fs::create_dir_all(dir).await?;
+    let (path, partial, mut file) = create_partial(dir, &file_name(url)).await?;
+    let written: anyhow::Result<()> = async {
+        let mut received = 0;
+        while let Some(chunk) = response.chunk().await? {
+            file.write_all(&chunk).await?;
+            received += chunk.len() as u64;
+            update_progress(downloads, url, |download| download.received = received);
+        }
+        file.flush().await?;
+        drop(file);
+        fs::rename(&partial, &path).await?;
+        Ok(())
+    }
+    .await;
+    if let Err(err) = written {
+        // The error of the download is more useful than the one of the removal
+        let _ = fs::remove_file(&partial).await;
+        return Err(err);
+    }
+    Ok(path)
+}
+
+/// Changes the last running download of the url
+fn update_progress<F>(downloads: &RwLock<Vec<Download>>, url: &str, update: F)
+where
+    F: FnOnce(&mut Download),
+{
+    let mut downloads = downloads.write().unwrap();
+    if let Some(download) = downloads
+        .iter_mut()
+        .rev()
+        .find(|download| download.url == url && download.state == DownloadState::Running)
+    {
+        update(download);
+    }
+}
+
+/// Name of the file from the last segment of the path of the url
+pub fn file_name(url: &str) -> String {
+    let name: String = Url::parse(url)
+        .ok()
+        .and_then(|url| {
+            url.path_segments()
+                .and_then(|mut segments| segments.next_back().map(String::from))
+        })
+        .unwrap_or_default()
+        .chars()
+        .filter(|c| !c.is_control() && *c != '/' && *c != '\\')
+        .collect();
+    match name.trim_start_matches('.') {
+        "" => String::from("enclosure"),
+        name => String::from(name),
+    }
+}
+
+/// Creates the temporary file of a download, returning the path of the file, the temporary path
+/// and the file. A number is added to the name if it's already used, by a file or by another
+/// download: the name is reserved by creating the temporary file only if it doesn't exist.
+async fn create_partial(dir: &Path, name: &str) -> io::Result<(PathBuf, PathBuf, fs::File)> {
+    let (stem, extension) = match name.rfind('.') {
+        Some(index) if index > 0 => (&name[..index], &name[index..]),
+        _ => (name, ""),
+    };
+    let names = std::iter::once(String::from(name))
+        .chain((1..).map(|count| format!("{} ({}){}", stem, count, extension)));
+    for name in names {
+        let path = dir.join(&name);
+        if path.exists() {
+            continue;
+        }
+        let partial = dir.join(format!("{}.part", name));
+        let created = fs::OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .open(&partial)
+            .await;
+        match created {
+            Ok(file) => return Ok((path, partial, file)),
+            Err(err) if err.kind() == io::ErrorKind::AlreadyExists => continue,
+            Err(err) => return Err(err),
+        }
+    }
+    unreachable!("the names are endless")
+}
+
+/// Size in bytes in a human readable unit
+pub fn format_size(bytes: u64) -> String {
+    const UNITS: [&str; 4] = ["KB", "MB", "GB", "TB"];
+    if bytes < 1000 {
+        return format!("{} B", bytes);
+    }
+    let mut size = bytes as f64 / 1000.0;
+    let mut unit = 0;
+    while size >= 1000.0 && unit < UNITS.len() - 1 {
+        size /= 1000.0;
+        unit += 1;
+    }
+    format!("{:.1} {}", size, UNITS[unit])
+}
+
+/// Duration in seconds as `H:MM:SS` or `M:SS`
+pub fn format_duration(seconds: u64) -> String {
+    let (hours, minutes, seconds) = (seconds / 3600, seconds / 60 % 60, seconds % 60);
+    if hours > 0 {
+        format!("{}:{:02}:{:02}", hours, minutes, seconds)
+    } else {
+        format!("{}:{:02}", minutes, seconds)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use tokio::{
+        io::{AsyncReadExt, AsyncWriteExt},
+        net::TcpListener,
+        time::sleep,
+    };
+
+    #[test]
+    fn test_file_name() {
+        assert_eq!(
+            file_name("https://example.com/podcast/episode%201.mp3?id=1"),
+            "episode%201.mp3"
+        );
+        assert_eq!(file_name("https://example.com/"), "enclosure");
+        assert_eq!(file_name("https://example.com/.hidden"), "hidden");
+        assert_eq!(file_name("not an url"), "enclosure");
+    }
+
+    /// Path of the download, its temporary file is created
+    async fn path(dir: &Path, name: &str) -> PathBuf {
+        let (path, partial, _) = create_partial(dir, name).await.unwrap();
+        assert_eq!(partial, PathBuf::from(format!("{}.part", path.display())));
+        assert!(partial.exists());
+        path
+    }
+
+    #[tokio::test]
+    async fn test_create_partial() {
+        let dir = std::env::temp_dir().join("feedrs_create_partial");
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        assert_eq!(path(&dir, "episode.mp3").await, dir.join("episode.mp3"));
+        // The name of a running download is not used again
+        assert_eq!(path(&dir, "episode.mp3").await, dir.join("episode (1).mp3"));
+        std::fs::write(dir.join("episode (2).mp3"), "").unwrap();
+        assert_eq!(path(&dir, "episode.mp3").await, dir.join("episode (3).mp3"));
+        std::fs::write(dir.join("episode"), "").unwrap();
+        assert_eq!(path(&dir, "episode").await, dir.join("episode (1)"));
+    }
+
+    #[test]
+    fn test_format_size() {
+        assert_eq!(format_size(999), "999 B");
+        assert_eq!(format_size(1500), "1.5 KB");
+        assert_eq!(format_size(25_300_000), "25.3 MB");
+        assert_eq!(format_size(2_000_000_000_000_000), "2000.0 TB");
+    }
+
+    #[test]
+    fn test_format_duration() {
+        assert_eq!(format_duration(59), "0:59");
+        assert_eq!(format_duration(61 * 60 + 2), "1:01:02");
+    }
+
+    #[tokio::test]
+    async fn test_download() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}/episode.mp3", listener.local_addr().unwrap());
+        tokio::spawn(async move {
+            let (mut socket, _) = listener.accept().await.unwrap();
+            let mut buf = [0; 1024];
+            let _ = socket.read(&mut buf).await.unwrap();
+            socket
+                .write_all(b"HTTP/1.1 200 OK\r\nContent-Length: 7\r\n\r\nepisode")
+                .await
+                .unwrap();
+        });
+        let dir = std::env::temp_dir().join("feedrs_download");
+        let _ = std::fs::remove_dir_all(&dir);
+        let downloads = Arc::new(RwLock::new(Vec::new()));
+        download(&Client::new(), &url, &dir, &downloads);
+        // The same url is not downloaded twice
+        download(&Client::new(), &url, &dir, &downloads);
+        assert_eq!(downloads.read().unwrap().len(), 1);
+        while downloads.read().unwrap()[0].state == DownloadState::Running {
+            sleep(Duration::from_millis(10)).await;
+        }
+        let download = downloads.read().unwrap()[0].clone();
+        let path = dir.join("episode.mp3");
+        assert_eq!(download.state, DownloadState::Done(path.clone()));
+        assert_eq!((download.received, download.total), (7, Some(7)));
+        assert_eq!(std::fs::read_to_string(path).unwrap(), "episode");
+        assert!(!dir.join("episode.mp3.part").exists());
+    }
+
+    #[tokio::test]
+    async fn test_download_interrupted() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}/episode.mp3", listener.local_addr().unwrap());
+        tokio::spawn(async move {
+            let (mut socket, _) = listener.accept().await.unwrap();
+            let mut request = Vec::new();
+            while !request.windows(4).any(|end| end == b"\r\n\r\n") {
+                let mut buf = [0; 1024];
+                let len = socket.read(&mut buf).await.unwrap();
+                if len == 0 {
+                    break;
+                }
+                request.extend_from_slice(&buf[..len]);
+            }
+            // The connection is closed before the end of the body
+            socket
+                .write_all(b"HTTP/1.1 200 OK\r\nContent-Length: 100\r\n\r\nepisode")
+                .await
+                .unwrap();
+        });
+        let dir = std::env::temp_dir().join("feedrs_download_interrupted");
+        let _ = std::fs::remove_dir_all(&dir);
+        let downloads = Arc::new(RwLock::new(Vec::new()));
+        download(&Client::new(), &url, &dir, &downloads);
+        while downloads.read().unwrap()[0].state == DownloadState::Running {
+            sleep(Duration::from_millis(10)).await;
+        }
+        let download = downloads.read().unwrap()[0].clone();
+        assert!(matches!(download.state, DownloadState::Failed(_)));
+        // The name is free for the next download
+        assert!(!dir.join("episode.mp3.part").exists());
+        assert!(!dir.join("episode.mp3").exists());
+    }
+
+    #[tokio::test]
+    async fn test_download_same_name() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap();
+        // Answers both requests together with the id in the query
+        tokio::spawn(async move {
+            let mut sockets = Vec::new();
+            for _ in 0..2 {
+                let (mut socket, _) = listener.accept().await.unwrap();
+                // The request can arrive in several reads
+                let mut request = Vec::new();
+                while !request.windows(4).any(|end| end == b"\r\n\r\n") {
+                    let mut buf = [0; 1024];
+                    let len = socket.read(&mut buf).await.unwrap();
+                    if len == 0 {
+                        break;
+                    }
+                    request.extend_from_slice(&buf[..len]);
+                }
+                let request = String::from_utf8_lossy(&request).into_owned();
+                let id = if request.contains("id=1 ") { "1" } else { "2" };
+                sockets.push((socket, id));
+            }
+            for (mut socket, id) in sockets {
+                let response = format!("HTTP/1.1 200 OK\r\nContent-Length: 1\r\n\r\n{}", id);
+                socket.write_all(response.as_bytes()).await.unwrap();
+            }
+        });
+        let dir = std::env::temp_dir().join("feedrs_download_same_name");
+        let _ = std::fs::remove_dir_all(&dir);
+        let downloads = Arc::new(RwLock::new(Vec::new()));
+        for id in 1..=2 {
+            let url = format!("http://{}/media.mp3?id={}", address, id);
+            download(&Client::new(), &url, &dir, &downloads);
+        }
+        while downloads
+            .read()
+            .unwrap()
+            .iter()
+            .any(|download| download.state == DownloadState::Running)
+        {
+            sleep(Duration::from_millis(10)).await;
+        }
+        let mut contents: Vec<String> = downloads
+            .read()
+            .unwrap()
+            .iter()
+            .map(|download| match &download.state {
+                DownloadState::Done(path) => std::fs::read_to_string(path).unwrap(),
+                state => panic!("{:?}", state),
+            })
+            .collect();
+        contents.sort();
+        assert_eq!(contents, vec!["1", "2"]);
+        assert!(dir.join("media.mp3").exists());
+        assert!(dir.join("media (1).mp3").exists());
+    }
+
+    #[tokio::test]
+    async fn test_download_failed() {
+        let dir = std::env::temp_dir().join("feedrs_download_failed");
+        let downloads = Arc::new(RwLock::new(Vec::new()));
+        download(
+            &Client::new(),
+            "http://127.0.0.1:9/episode.mp3",
+            &dir,
+            &downloads,
+        );
+        while downloads.read().unwrap()[0].state == DownloadState::Running {
+            sleep(Duration::from_millis(10)).await;
+        }
+        let download = downloads.read().unwrap()[0].clone();
+        assert!(matches!(download.state, DownloadState::Failed(_)));
+        assert!(download.finished.is_some());
+    }
+}
@@ -0,0 +1,252 @@
+use chrono::DateTime;
+use serde::Deserialize;
+use serde_json::Value;
+
+use crate::content::{
+    non_empty, parse_html, parse_links, Article, Enclosure, FeedMetadata, ParsedFeed, UpdateHints,
+};
+
+/// Prefix of the version of the JSON Feed documents
+const VERSION_PREFIX: &str = "https://jsonfeed.org/version/";
+
+// Top level object of a JSON Feed, only the fields we use are deserialized
+#[derive(Deserialize, Debug)]
+struct JsonFeed {
+    version: String,
+    title: Option<String>,
+    description: Option<String>,
+    home_page_url: Option<String>,
+    // Added in the version 1.1
+    language: Option<String>,
+    icon: Option<String>,
+    favicon: Option<String>,
+    // Authors of the items that don't have their own
+    #[serde(default)]
+    authors: Vec<Author>,
+    author: Option<Author>,
+    items: Vec<Item>,
+}
+
+#[derive(Deserialize, Debug)]
+struct Item {
+    // Should be a string, but some publishers use numbers or omit it
+    #[serde(default)]
+    id: Value,
+    url: Option<String>,
+    title: Option<String>,
+    summary: Option<String>,
+    content_html: Option<String>,
+    content_text: Option<String>,
+    date_published: Option<String>,
+    date_modified: Option<String>,
+    #[serde(default)]
+    authors: Vec<Author>,
+    // Single author of the version 1.0, replaced by the authors
+    author: Option<Author>,
+    #[serde(default)]
+    tags: Vec<String>,
+    #[serde(default)]
+    attachments: Vec<Attachment>,
+}
+
+#[derive(Deserialize, Debug)]
+struct Attachment {
+    url: String,
+    mime_type: Option<String>,
+    size_in_bytes: Option<f64>,
+    duration_in_seconds: Option<f64>,
+}
+
+#[derive(Deserialize, Debug)]
+struct Author {
+    name: Option<String>,
+}
+
+/// Parses a JSON Feed document, version 1.0 or 1.1
+pub fn parse_json_feed(source: &str, content: &str) -> anyhow::Result<ParsedFeed> {
+    let feed: JsonFeed = serde_json::from_str(content)?;
+    if !feed.version.starts_with(VERSION_PREFIX) {
+        anyhow::bail!("unknown JSON Feed version {}", feed.version);
+    }
+    let feed_authors = author_names(feed.authors, feed.author);
+    let articles = feed
+        .items
+        .into_iter()
+        .map(|item| {
+            // Fall back to the url of the item
+            let id = match item.id {
+                Value::String(id) if !id.trim().is_empty() => id,
+                Value::Null | Value::String(_) => item.url.clone().unwrap_or_default(),
+                id => id.to_string(),
+            };
+            let links = parse_links(
+                item.url.as_deref(),
+                &[item.content_html.as_deref().unwrap_or("")],
+            );
+            let content_html = item.content_html.clone().unwrap_or_default();
+            // The html content is preferred when both are present
+            let content = match (item.content_html, item.content_text) {
+                (Some(html), _) => parse_html(&html),
+                (None, Some(text)) => text,
+                (None, None) => String::new(),
+            };
+            let mut authors = author_names(item.authors, item.author);
+            if authors.is_empty() {
+                authors = feed_authors.clone();
+            }
+            let date = item
+                .date_modified
+                .or(item.date_published)
+                .and_then(|date| DateTime::parse_from_rfc3339(&date).ok());
+            Article {
+                id,
+                source: String::from(source),
+                title: item.title.unwrap_or_default(),
+                sub_title: item.summary.unwrap_or_default(),
+                content,
+                date,
+                read: false,
+                starred: false,
+                link: item.url,
+                authors,
+                categories: item.tags,
+                comments: None,
+                enclosures: item
+                    .attachments
+                    .into_iter()
+                    .map(|attachment| Enclosure {
+                        url: attachment.url,
+                        mime_type: attachment.mime_type,
+                        // The numbers are not always integers
+                        length: attachment.size_in_bytes.map(|size| size as u64),
+                        duration: attachment
+                            .duration_in_seconds
+                            .map(|duration| duration as u64),
+                    })
+                    .collect(),
+                links,
+                // The summary is plain text
+                sub_title_html: String::new(),
+                content_html,
+            }
+        })
+        .collect();
+    let metadata = FeedMetadata {
+        title: non_empty(feed.title.as_deref()),
+        description: non_empty(feed.description.as_deref()),
+        site_link: non_empty(feed.home_page_url.as_deref()),
+        language: non_empty(feed.language.as_deref()),
+        icon: non_empty(feed.icon.as_deref().or(feed.favicon.as_deref())),
+        generator: None,
+    };
+    // JSON Feed has no hints on when to request it
+    Ok(ParsedFeed {
+        articles,
+        hints: UpdateHints::default(),
+        metadata,
+    })
+}
+
+/// Names of the authors of the version 1.1 and of the single author of the version 1.0
+fn author_names(authors: Vec<Author>, author: Option<Author>) -> Vec<String> {
+    authors
+        .into_iter()
+        .chain(author)
+        .filter_map(|author| author.name)
+        .collect()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_parse_json_feed() {
+        let content = std::fs::read_to_string("tests/feeds/json_feed.json").unwrap();
+        let feed = parse_json_feed("source", &content).unwrap();
+        assert_eq!(
+            feed.articles,
+            vec![
+                Article {
+                    id: String::from("https://example.com/2"),
+                    source: String::from("source"),
+                    title: String::from("Second"),
+                    sub_title: String::from("Second article"),
+                    content: String::from("Hello World!"),
+                    date: DateTime::parse_from_rfc3339("2021-02-02T10:00:00+01:00").ok(),
+                    read: false,
+                    starred: false,
+                    link: Some(String::from("https://example.com/2")),
+                    authors: vec![String::from("Item Author")],
+                    categories: vec![String::from("rust"), String::from("release")],
+                    comments: None,
+                    enclosures: vec![Enclosure {
+                        url: String::from("https://example.com/2.mp3"),
+                        mime_type: Some(String::from("audio/mpeg")),
+                        length: Some(1024),
+                        duration: Some(60),
+                    }],
+                    links: Vec::new(),
+                    sub_title_html: String::new(),
+                    content_html: String::from("<p>Hello <b>World</b>!</p>"),
+                },
+                Article {
+                    id: String::from("1"),
+                    source: String::from("source"),
+                    title: String::new(),
+                    sub_title: String::new(),
+                    content: String::from("Only text"),
+                    date: DateTime::parse_from_rfc3339("2021-01-01T10:00:00Z").ok(),
+                    read: false,
+                    starred: false,
+                    // Inherited from the feed
+                    authors: vec![String::from("Feed Author")],
+                    ..Default::default()
+                },
+            ]
+        );
+        assert_eq!(feed.hints, UpdateHints::default());
+        assert_eq!(
+            feed.metadata,
+            FeedMetadata {
+                title: Some(String::from("JSON Feed")),
+                description: Some(String::from("Feed in JSON")),
+                site_link: Some(String::from("https://example.com/")),
+                language: Some(String::from("en")),
+                icon: Some(String::from("https://example.com/favicon.ico")),
+                generator: None,
+            }
+        );
+    }
+
+    #[test]
+    fn test_parse_json_feed_url() {
+        let content = r#"{
+            "version": "https://jsonfeed.org/version/1",
+            "items": [{"url": "https://example.com/1", "content_text": "text"}]
+        }"#;
+        let feed = parse_json_feed("source", content).unwrap();
+        assert_eq!(feed.articles[0].id, "https://example.com/1");
+    }
+
+    #[test]
+    fn test_parse_json_feed_author() {
+        let content = r#"{
+            "version": "https://jsonfeed.org/version/1",
+            "author": {"name": "Feed Author"},
+            "items": [
+                {"id": "1", "author": {"name": "Item Author"}},
+                {"id": "2"}
+            ]
+        }"#;
+        let feed = parse_json_feed("source", content).unwrap();
+        assert_eq!(feed.articles[0].authors, vec![String::from("Item Author")]);
+        assert_eq!(feed.articles[1].authors, vec![String::from("Feed Author")]);
+    }
+
+    #[test]
+    fn test_parse_json_feed_err() {
+        assert!(parse_json_feed("source", "{}").is_err());
+        assert!(parse_json_feed("source", r#"{"version": "1", "items": []}"#).is_err());
+    }
+}
@@ -1,12 +1,17 @@
 use crossterm::{
-    event::{Event, KeyCode, KeyEvent},
+    event::{Event, KeyCode},
     execute,
     terminal::{disable_raw_mode, enable_raw_mode, EnterAlternateScreen, LeaveAlternateScreen},
 };
 use std::{
     io,
-    io::{stdout, Write},
-    sync::{Arc, Mutex},
+    io::{stdout, Stdout, Write},
+    process,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Mutex, MutexGuard,
+    },
+    time::Duration,
 };
 use tui::{backend::CrosstermBackend, Terminal};
 
@@ -14,27 +19,61 @@ mod app;
 mod configuration;
 mod content;
 mod database;
+mod download;
+mod json_feed;
+mod opml;
+mod panics;
+mod render;
+mod search;
 mod update;
 
-use crate::app::App;
+use crate::{app::App, configuration::Command};
 
-fn input_thread(inputs: &Arc<Mutex<Vec<KeyEvent>>>) {
+/// Stops the input thread from reading the terminal while another program uses it
+#[derive(Clone, Default)]
+struct InputPause {
+    paused: Arc<AtomicBool>,
+    // Held by the input thread while it reads the terminal
+    reading: Arc<Mutex<()>>,
+}
+
+impl InputPause {
+    /// Waits for the input thread to stop reading, it's paused until the guard is dropped and
+    /// `resume` is called
+    fn pause(&self) -> MutexGuard<'_, ()> {
+        self.paused.store(true, Ordering::Relaxed);
+        self.reading.lock().unwrap()
+    }
+
+    fn resume(&self) {
+        self.paused.store(false, Ordering::Relaxed);
+    }
+}
+
+fn input_thread(inputs: &Arc<Mutex<Vec<Event>>>, running: &Arc<AtomicBool>, pause: &InputPause) {
     let inputs = Arc::clone(inputs);
-    tokio::spawn(async move {
-        loop {
-            // Blocks until event/input
-            match crossterm::event::read() {
-                Ok(event) => {
-                    if let Event::Key(event) = event {
-                        inputs.lock().unwrap().push(event);
-                        if event.code == KeyCode::Char('q') {
-                            return;
-                        }
+    let running = Arc::clone(running);
+    let pause = pause.clone();
+    // Blocking task so it doesn't stop a worker of the runtime while waiting for the input
+    tokio::task::spawn_blocking(move || {
+        // Waits for an event with a timeout to check if the application is still running
+        while running.load(Ordering::Relaxed) {
+            if pause.paused.load(Ordering::Relaxed) {
+                std::thread::sleep(Duration::from_millis(50));
+                continue;
+            }
+            let _reading = pause.reading.lock().unwrap();
+            match crossterm::event::poll(Duration::from_millis(50)) {
+                Ok(true) => match crossterm::event::read() {
+                    // The size is read again by the next draw
+                    Ok(event @ Event::Key(_)) | Ok(event @ Event::Resize(_, _)) => {
+                        inputs.lock().unwrap().push(event)
                     }
-                }
-                Err(err) => {
-                    panic!("{}", err);
-                }
+                    Ok(_) => {}
+                    Err(err) => panic!("{}", err),
+                },
+                Ok(false) => {}
+                Err(err) => panic!("{}", err),
             }
         }
     });
@@ -46,10 +85,62 @@ fn close_application() -> crossterm::Result<()> {
     Ok(())
 }
 
+/// Runs the opener with the link. The interface is suspended until the command exits, so a
+/// terminal browser can use the terminal. The errors are shown in the status line.
+fn open_link(
+    app: &mut App<CrosstermBackend<Stdout>>,
+    opener: &[String],
+    link: &str,
+    pause: &InputPause,
+) {
+    let args = configuration::opener_args(opener, link);
+    let (program, args) = match args.split_first() {
+        Some(args) => args,
+        None => return,
+    };
+    let reading = pause.pause();
+    let status = close_application()
+        .map_err(|err| format!("Failed to suspend the interface: {}", err))
+        .and_then(|_| {
+            // The command blocks this worker of the runtime, the other tasks keep running
+            tokio::task::block_in_place(|| process::Command::new(program).args(args).status())
+                .map_err(|err| format!("Failed to run {}: {}", program, err))
+        });
+    // The interface is restored even if the command didn't run
+    let restored = enable_raw_mode()
+        .and_then(|_| execute!(stdout(), EnterAlternateScreen))
+        .map_err(|err| err.to_string())
+        // The screen was used by the command, everything has to be drawn again
+        .and_then(|_| app.terminal.clear().map_err(|err| err.to_string()));
+    drop(reading);
+    pause.resume();
+    app.error = match (status, restored) {
+        (_, Err(err)) => Some(format!("Failed to restore the interface: {}", err)),
+        (Ok(status), _) if status.success() => None,
+        (Ok(status), _) => Some(format!("{} exited with {}", program, status)),
+        (Err(err), _) => Some(err),
+    };
+}
+
+/// Copies the text in the clipboard of the terminal with the OSC 52 sequence, it works also over
+/// ssh but some terminals ignore it
+fn yank(text: &str) -> io::Result<()> {
+    let mut std_out = stdout();
+    write!(std_out, "\x1b]52;c;{}\x07", base64::encode(text))?;
+    std_out.flush()
+}
+
 #[tokio::main]
 async fn main() -> anyhow::Result<()> {
     // Read configuration
     let config = configuration::config(std::env::args())?;
+    // Run the command instead of the interface
+    match &config.command {
+        Some(Command::ImportOpml(path)) => return opml::import_opml(&config, path),
+        Some(Command::ExportOpml) => return opml::export_opml(&config),
+        Some(Command::Search(query)) => return search::search_command(&config, query).await,
+        None => {}
+    }
     // Create database pool
     let pool = Arc::new(database::get_database(&config.cache_path).await?);
     // Initialize UI
@@ -59,46 +150,104 @@ async fn main() -> anyhow::Result<()> {
     execute!(std_out, EnterAlternateScreen)?;
     let backend = CrosstermBackend::new(std_out);
     let terminal = Terminal::new(backend)?;
-    let mut app = App::new(terminal);
+    let mut app = App::new(terminal, &pool, &config);
     // Request all the content
     database::get_all(&pool, &app.content).await?;
+    *app.status.write().unwrap() = database::get_fetch_status(&pool).await?;
+    *app.feeds.write().unwrap() = database::get_feed_metadata(&pool).await?;
     // Draws the area every 50 milliseconds
     let mut interval = tokio::time::interval(tokio::time::Duration::from_millis(50));
     // Shared collection of events with input thread
     let inputs = Arc::new(Mutex::new(Vec::new()));
-    // Sync list of events
+    // Set to false to stop the input thread when closing the application
+    let running = Arc::new(AtomicBool::new(true));
+    // Paused while an external command runs in the terminal
+    let pause = InputPause::default();
     // Starts user input thread
-    input_thread(&inputs);
+    input_thread(&inputs, &running, &pause);
     // Starts update thread
-    update::update_thread(&config, &pool, &app.content);
+    app.refresh = Some(update::update_thread(
+        &config,
+        &pool,
+        &app.content,
+        &app.status,
+        &app.fetching,
+        &app.feeds,
+    ));
+    // Starts the thread deleting the expired articles
+    database::cleanup_thread(&config, &pool, &app.content, &app.task_errors);
     // Main loop
     loop {
         // Drawing tick
         interval.tick().await;
 
-        let events: Vec<KeyEvent>;
+        let events: Vec<Event>;
         // Consume all the inputs in the shared collections
         {
             events = inputs.lock().unwrap().drain(0..).collect();
         }
 
         for event in events {
-            match event.code {
-                KeyCode::Char('h') | KeyCode::Left => {
-                    app.set_view_article(false);
+            let event = match event {
+                Event::Key(event) => event,
+                Event::Resize(_, _) => {
+                    app.resize_event();
+                    continue;
+                }
+                _ => continue,
+            };
+            app.error = None;
+            if app.is_prompting() {
+                app.prompt_key_event(event);
+                continue;
+            }
+            if app.is_picking_link() {
+                match event.code {
+                    KeyCode::Char('o') | KeyCode::Enter => {
+                        if let Some(link) = app.pick_link() {
+                            open_link(&mut app, &config.opener, &link, &pause);
+                        }
+                    }
+                    KeyCode::Char('y') => {
+                        if let Some(link) = app.pick_link() {
+                            if let Err(err) = yank(&link) {
+                                app.error = Some(format!("Failed to copy the link: {}", err));
+                            }
+                        }
+                    }
+                    _ => app.link_picker_key_event(event),
                 }
+                continue;
+            }
+            match event.code {
+                KeyCode::Char('h') | KeyCode::Left => app.left_key_event(),
                 KeyCode::Char('j') | KeyCode::Down => app.down_key_event(),
                 KeyCode::Char('k') | KeyCode::Up => app.up_key_event(),
-                KeyCode::Char('l') | KeyCode::Right => {
-                    app.set_view_article(true);
-                }
-                KeyCode::Enter => {
-                    app.set_view_article(true);
-                }
-                KeyCode::Esc => {
-                    app.set_view_article(false);
+                KeyCode::Char('l') | KeyCode::Right => app.right_key_event(),
+                KeyCode::Tab => app.switch_focus_event(),
+                KeyCode::Char('p') => app.toggle_preview_event(),
+                KeyCode::Char('/') => app.search_event(),
+                KeyCode::Char('F') => app.archive_search_event(),
+                KeyCode::Char('n') => app.next_match_event(true),
+                KeyCode::Char('N') => app.next_match_event(false),
+                KeyCode::Char('m') => app.toggle_read_event(),
+                KeyCode::Char('M') => app.mark_all_read_event(),
+                KeyCode::Char('s') => app.toggle_starred_event(),
+                KeyCode::Char('S') => app.toggle_starred_view_event(),
+                KeyCode::Char('r') => app.refresh_event(),
+                KeyCode::Char('R') => app.refresh_all_event(),
+                KeyCode::Char('e') => app.next_enclosure_event(),
+                KeyCode::Char('d') => app.download_event(),
+                KeyCode::Char('o') => {
+                    if let Some(link) = app.link() {
+                        open_link(&mut app, &config.opener, &link, &pause);
+                    }
                 }
+                KeyCode::Char('f') => app.link_picker_event(),
+                KeyCode::Enter => app.right_key_event(),
+                KeyCode::Esc => app.escape_event(),
                 KeyCode::Char('q') => {
+                    running.store(false, Ordering::Relaxed);
                     close_application()?;
                     return Ok(());
                 }
@@ -0,0 +1,240 @@
+use quick_xml::{
+    events::{BytesDecl, BytesEnd, BytesStart, BytesText, Event},
+    Reader, Writer,
+};
+use std::{collections::BTreeMap, fs, io::Cursor, path::Path};
+
+use crate::configuration::{self, Config, Source};
+
+/// Parses the outlines of an OPML file into sources. The outlines without an `xmlUrl` are folders,
+/// their path is added to the tags of the sources inside them together with the `category`
+/// attribute.
+pub fn parse_opml(content: &str) -> anyhow::Result<Vec<Source>> {
+    let mut reader = Reader::from_str(content);
+    reader.trim_text(true);
+    let mut buf = Vec::new();
+    let mut sources = Vec::new();
+    // Titles of the open outlines, None for the outlines that are not folders
+    let mut folders: Vec<Option<String>> = Vec::new();
+    loop {
+        match reader.read_event(&mut buf)? {
+            Event::Start(ref element) if element.name() == b"outline" => {
+                let outline = parse_outline(&reader, element)?;
+                match outline {
+                    Outline::Source(source) => {
+                        sources.push(with_folder(source, &folders));
+                        folders.push(None);
+                    }
+                    Outline::Folder(title) => folders.push(Some(title)),
+                }
+            }
+            Event::Empty(ref element) if element.name() == b"outline" => {
+                // An empty folder has no sources to add
+                if let Outline::Source(source) = parse_outline(&reader, element)? {
+                    sources.push(with_folder(source, &folders));
+                }
+            }
+            Event::End(ref element) if element.name() == b"outline" => {
+                folders.pop();
+            }
+            Event::Eof => break,
+            _ => {}
+        }
+        buf.clear();
+    }
+    Ok(sources)
+}
+
+enum Outline {
+    Source(Source),
+    Folder(String),
+}
+
+fn parse_outline(reader: &Reader<&[u8]>, element: &BytesStart) -> anyhow::Result<Outline> {
+    let mut url = None;
+    let mut text = None;
+    let mut title = None;
+    let mut tags = Vec::new();
+    for attribute in element.attributes() {
+        let attribute = attribute?;
+        let value = attribute.unescape_and_decode_value(reader)?;
+        match attribute.key {
+            b"xmlUrl" => url = Some(value),
+            b"text" => text = Some(value),
+            b"title" => title = Some(value),
+            // Comma separated list of slash delimited categories
+            b"category" => tags.extend(
+                value
+                    .split(',')
+                    .map(|category| category.trim().trim_matches('/'))
+                    .filter(|category| !category.is_empty())
+                    .map(String::from),
+            ),
+            _ => {}
+        }
+    }
+    // The text is required so it's set to the url when there is no name
+    let name = title
+        .or(text)
+        .filter(|name| !name.is_empty() && Some(name) != url.as_ref());
+    Ok(match url {
+        Some(url) => {
+            let mut source = Source::new(url);
+            source.name = name;
+            source.tags = tags;
+            Outline::Source(source)
+        }
+        None => Outline::Folder(name.unwrap_or_default()),
+    })
+}
+
+/// Adds the path of the folders as the first tag of the source
+fn with_folder(mut source: Source, folders: &[Option<String>]) -> Source {
+    let path: Vec<&str> = folders.iter().flatten().map(String::as_str).collect();
+    if !path.is_empty() {
+        let path = path.join("/");
+        source.tags.retain(|tag| *tag != path);
+        source.tags.insert(0, path);
+    }
+    source
+}
+
+/// Folder of the exported OPML
+#[derive(Default)]
+struct Folder<'a> {
+    folders: BTreeMap<&'a str, Folder<'a>>,
+    sources: Vec<&'a Source>,
+}
+
+/// Writes the sources as an OPML document. The first tag of a source is used as the path of its
+/// folder, while all the other tags are written as categories.
+pub fn write_opml(sources: &[Source]) -> anyhow::Result<String> {
+    let mut root = Folder::default();
+    for source in sources {
+        let mut folder = &mut root;
+        if let Some(path) = source.tags.first() {
+            for name in path.split('/').filter(|name| !name.is_empty()) {
+                folder = folder.folders.entry(name).or_default();
+            }
+        }
+        folder.sources.push(source);
+    }
+
+    let mut writer = Writer::new_with_indent(Cursor::new(Vec::new()), b' ', 2);
+    writer.write_event(Event::Decl(BytesDecl::new(b"1.0", Some(b"UTF-8"), None)))?;
+    writer.write_event(Event::Start(
+        BytesStart::borrowed_name(b"opml").with_attributes(vec![("version", "2.0")]),
+    ))?;
+    writer.write_event(Event::Start(BytesStart::borrowed_name(b"head")))?;
+    writer.write_event(Event::Start(BytesStart::borrowed_name(b"title")))?;
+    writer.write_event(Event::Text(BytesText::from_plain_str("feedrs")))?;
+    writer.write_event(Event::End(BytesEnd::borrowed(b"title")))?;
+    writer.write_event(Event::End(BytesEnd::borrowed(b"head")))?;
+    writer.write_event(Event::Start(BytesStart::borrowed_name(b"body")))?;
+    write_folder(&mut writer, &root)?;
+    writer.write_event(Event::End(BytesEnd::borrowed(b"body")))?;
+    writer.write_event(Event::End(BytesEnd::borrowed(b"opml")))?;
+
+    Ok(String::from_utf8(writer.into_inner().into_inner())?)
+}
+
+fn write_folder(writer: &mut Writer<Cursor<Vec<u8>>>, folder: &Folder) -> anyhow::Result<()> {
+    for source in &folder.sources {
+        let text = source.name.as_ref().unwrap_or(&source.url);
+        let mut element = BytesStart::borrowed_name(b"outline");
+        element.push_attribute(("type", "rss"));
+        element.push_attribute(("text", text.as_str()));
+        if let Some(name) = &source.name {
+            element.push_attribute(("title", name.as_str()));
+        }
+        element.push_attribute(("xmlUrl", source.url.as_str()));
+        let categories: Vec<String> = source
+            .tags
+            .iter()
+            .skip(1)
+            .map(|tag| format!("/{}", tag))
+            .collect();
+        if !categories.is_empty() {
+            element.push_attribute(("category", categories.join(",").as_str()));
+        }
+        writer.write_event(Event::Empty(element))?;
+    }
+    for (name, sub_folder) in &folder.folders {
+        writer.write_event(Event::Start(
+            BytesStart::borrowed_name(b"outline")
+                .with_attributes(vec![("text", *name), ("title", *name)]),
+        ))?;
+        write_folder(writer, sub_folder)?;
+        writer.write_event(Event::End(BytesEnd::borrowed(b"outline")))?;
+    }
+    Ok(())
+}
+
+/// Adds the sources in the OPML file to the config file
+pub fn import_opml(config: &Config, path: &Path) -> anyhow::Result<()> {
+    let sources = parse_opml(&fs::read_to_string(path)?)?;
+    let total = sources.len();
+    let added = configuration::add_sources(&config.config_path, sources)?;
+    println!(
+        "Imported {} of {} sources into {}",
+        added,
+        total,
+        config.config_path.display()
+    );
+    Ok(())
+}
+
+/// Prints the sources in the config file as OPML
+pub fn export_opml(config: &Config) -> anyhow::Result<()> {
+    let sources = configuration::read_sources(&config.config_path)?;
+    println!("{}", write_opml(&sources)?);
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn source(url: &str, name: Option<&str>, tags: &[&str]) -> Source {
+        let mut source = Source::new(String::from(url));
+        source.name = name.map(String::from);
+        source.tags = tags.iter().map(|tag| String::from(*tag)).collect();
+        source
+    }
+
+    #[test]
+    fn test_parse_opml() {
+        let content = fs::read_to_string("tests/opml/subscriptions.opml").unwrap();
+        let expected = vec![
+            source("https://example.com/feed.xml", Some("No folder"), &[]),
+            source(
+                "https://blog.rust-lang.org/feed.xml",
+                Some("Rust Blog"),
+                &["Tech"],
+            ),
+            source(
+                "https://lwn.net/headlines/rss",
+                Some("LWN & friends"),
+                &["Tech/Linux", "news", "kernel"],
+            ),
+            source("https://example.com/atom.xml", None, &["blog"]),
+        ];
+        assert_eq!(parse_opml(&content).unwrap(), expected);
+    }
+
+    #[test]
+    fn test_parse_opml_err() {
+        assert!(parse_opml("<opml><body></opml>").is_err());
+    }
+
+    #[test]
+    fn test_write_opml_round_trip() {
+        let content = fs::read_to_string("tests/opml/subscriptions.opml").unwrap();
+        let mut sources = parse_opml(&content).unwrap();
+        let mut result = parse_opml(&write_opml(&sources).unwrap()).unwrap();
+        // The sources are written grouped by folder
+        sources.sort_by(|a, b| a.url.cmp(&b.url));
+        result.sort_by(|a, b| a.url.cmp(&b.url));
+        assert_eq!(result, sources);
+    }
+}
@@ -0,0 +1,40 @@
+use std::{
+    cell::Cell,
+    panic::{self, AssertUnwindSafe},
+    sync::Once,
+};
+
+thread_local! {
+    // Set while the thread runs a closure that can panic without printing it
+    static QUIET: Cell<bool> = const { Cell::new(false) };
+}
+
+/// Runs the closure and returns `None` if it panics. The panic is not printed, the message would
+/// be written over the interface.
+pub fn catch_quietly<T, F: FnOnce() -> T>(f: F) -> Option<T> {
+    static HOOK: Once = Once::new();
+    HOOK.call_once(|| {
+        let default = panic::take_hook();
+        panic::set_hook(Box::new(move |info| {
+            if !QUIET.with(Cell::get) {
+                default(info);
+            }
+        }));
+    });
+    QUIET.with(|quiet| quiet.set(true));
+    let result = panic::catch_unwind(AssertUnwindSafe(f));
+    QUIET.with(|quiet| quiet.set(false));
+    result.ok()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_catch_quietly() {
+        assert_eq!(catch_quietly(|| 1), Some(1));
+        assert_eq!(catch_quietly(|| -> i32 { panic!("html") }), None);
+        assert!(!QUIET.with(Cell::get));
+    }
+}
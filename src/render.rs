@@ -0,0 +1,505 @@
+use html2text::render::text_renderer::{
+    RichAnnotation, TaggedLine, TaggedLineElement, TextDecorator,
+};
+use std::sync::Arc;
+use tui::{
+    style::{Color, Modifier, Style},
+    text::{Span, Spans},
+};
+use unicode_segmentation::UnicodeSegmentation;
+use unicode_width::UnicodeWidthStr;
+
+use crate::{content::Article, panics::catch_quietly};
+
+/// Narrowest width the html is rendered at, html2text can't indent the nested blocks in less
+const MIN_WIDTH: usize = 20;
+/// Rendered articles kept, enough for the article in the view and the preview at a few widths
+const CACHE_SIZE: usize = 8;
+
+/// Html of the sub title and content of an article rendered at a width, `None` when the plain
+/// text has to be shown instead
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct RenderedArticle {
+    pub sub_title: Option<Vec<Spans<'static>>>,
+    pub content: Option<Vec<Spans<'static>>>,
+}
+
+impl RenderedArticle {
+    pub fn new(article: &Article, width: u16) -> RenderedArticle {
+        let render = |html: &str| {
+            Some(html)
+                .filter(|html| !html.trim().is_empty())
+                .and_then(|html| render_html(html, usize::from(width)))
+        };
+        RenderedArticle {
+            sub_title: render(&article.sub_title_html),
+            content: render(&article.content_html),
+        }
+    }
+}
+
+/// Articles rendered at the width they are drawn, the html is rendered again only when the
+/// article or the width changes and not at every draw
+#[derive(Debug, Default)]
+pub struct RenderCache {
+    // Oldest first
+    entries: Vec<(Arc<Article>, u16, RenderedArticle)>,
+}
+
+impl RenderCache {
+    /// Returns the article rendered at the width, rendering it if it's not cached
+    pub fn get(&mut self, article: &Arc<Article>, width: u16) -> &RenderedArticle {
+        // The flags of the article can change, the rendered html stays the same
+        let position = self.entries.iter().position(|(cached, cached_width, _)| {
+            *cached_width == width
+                && cached.id == article.id
+                && cached.source == article.source
+                && cached.sub_title_html == article.sub_title_html
+                && cached.content_html == article.content_html
+        });
+        let index = match position {
+            Some(index) => index,
+            None => {
+                if self.entries.len() >= CACHE_SIZE {
+                    self.entries.remove(0);
+                }
+                let rendered = RenderedArticle::new(article, width);
+                self.entries.push((Arc::clone(article), width, rendered));
+                self.entries.len() - 1
+            }
+        };
+        &self.entries[index].2
+    }
+
+    /// Forgets all the rendered articles, the widths they were rendered at are not used anymore
+    pub fn clear(&mut self) {
+        self.entries.clear();
+    }
+}
+
+/// Decorator of html2text that only annotates the text, the styles replace the markers it adds
+/// around the strong and code text
+#[derive(Clone, Copy, Debug, Default)]
+struct StyleDecorator;
+
+impl TextDecorator for StyleDecorator {
+    type Annotation = RichAnnotation;
+
+    fn decorate_link_start(&mut self, url: &str) -> (String, Self::Annotation) {
+        (String::new(), RichAnnotation::Link(String::from(url)))
+    }
+
+    fn decorate_link_end(&mut self) -> String {
+        String::new()
+    }
+
+    fn decorate_em_start(&mut self) -> (String, Self::Annotation) {
+        (String::new(), RichAnnotation::Emphasis)
+    }
+
+    fn decorate_em_end(&mut self) -> String {
+        String::new()
+    }
+
+    fn decorate_strong_start(&mut self) -> (String, Self::Annotation) {
+        (String::new(), RichAnnotation::Strong)
+    }
+
+    fn decorate_strong_end(&mut self) -> String {
+        String::new()
+    }
+
+    fn decorate_strikeout_start(&mut self) -> (String, Self::Annotation) {
+        (String::new(), RichAnnotation::Strikeout)
+    }
+
+    fn decorate_strikeout_end(&mut self) -> String {
+        String::new()
+    }
+
+    fn decorate_code_start(&mut self) -> (String, Self::Annotation) {
+        (String::new(), RichAnnotation::Code)
+    }
+
+    fn decorate_code_end(&mut self) -> String {
+        String::new()
+    }
+
+    fn decorate_preformat_first(&mut self) -> Self::Annotation {
+        RichAnnotation::Preformat(false)
+    }
+
+    fn decorate_preformat_cont(&mut self) -> Self::Annotation {
+        RichAnnotation::Preformat(true)
+    }
+
+    fn decorate_image(&mut self, title: &str) -> (String, Self::Annotation) {
+        (String::from(title), RichAnnotation::Image)
+    }
+
+    fn make_subblock_decorator(&self) -> Self {
+        StyleDecorator
+    }
+
+    fn finalise(self) -> Vec<TaggedLine<Self::Annotation>> {
+        Vec::new()
+    }
+}
+
+/// Renders the html in styled lines wrapped to the width. Returns `None` if html2text fails to
+/// render it, so the plain text can be shown instead.
+pub fn render_html(html: &str, width: usize) -> Option<Vec<Spans<'static>>> {
+    let width = width.max(MIN_WIDTH);
+    // html2text panics on some documents, it must not close the interface
+    let lines = catch_quietly(|| {
+        html2text::parse(presentational_tags(html).as_bytes())
+            .render(width, StyleDecorator)
+            .into_lines()
+    })?;
+    let mut lines: Vec<Spans> = lines.iter().map(line_spans).collect();
+    // Same as the plain text, without the empty lines around the blocks
+    while lines.last().is_some_and(|line| line.width() == 0) {
+        lines.pop();
+    }
+    let empty = lines.iter().take_while(|line| line.width() == 0).count();
+    Some(lines.split_off(empty))
+}
+
+/// Number of rows the line takes in a paragraph wrapped at the width without trimming. The words
+/// are wrapped in the same way as the paragraph of tui, it doesn't give the position of the rows.
+pub fn wrapped_rows(line: &Spans, width: u16) -> usize {
+    const NBSP: &str = "\u{00a0}";
+    if width == 0 {
+        return 0;
+    }
+    let symbols = line
+        .0
+        .iter()
+        .flat_map(|span| span.content.graphemes(true))
+        .filter(|symbol| *symbol != "\n");
+    let mut rows = 1;
+    // Symbols of the current row and their width
+    let mut current: Vec<&str> = Vec::new();
+    let mut current_width: u16 = 0;
+    // Position in the current row of the end of its last word
+    let mut word_end: usize = 0;
+    let mut previous_whitespace = false;
+    for symbol in symbols {
+        let symbol_width = symbol.width() as u16;
+        // Characters wider than the row are not shown
+        if symbol_width > width {
+            continue;
+        }
+        let whitespace = symbol.chars().all(char::is_whitespace);
+        if whitespace && !previous_whitespace && symbol != NBSP {
+            word_end = current.len();
+        }
+        current.push(symbol);
+        current_width += symbol_width;
+        if current_width > width {
+            // The last word goes in the next row, or the row is cut if it has no word break
+            let truncate_at = if word_end != 0 {
+                word_end
+            } else {
+                current.len() - 1
+            };
+            let remainder: Vec<&str> = current[truncate_at..]
+                .iter()
+                .copied()
+                .skip_while(|symbol| symbol.chars().all(char::is_whitespace))
+                .collect();
+            rows += 1;
+            current_width = remainder.iter().map(|symbol| symbol.width() as u16).sum();
+            current = remainder;
+            word_end = 0;
+            previous_whitespace = false;
+            continue;
+        }
+        previous_whitespace = whitespace;
+    }
+    rows
+}
+
+/// Replaces the `b` and `i` tags by `strong` and `em`, html2text only annotates the latter
+fn presentational_tags(html: &str) -> String {
+    let mut result = String::with_capacity(html.len());
+    let mut rest = html;
+    while let Some(index) = rest.find('<') {
+        result.push_str(&rest[..index]);
+        rest = &rest[index + 1..];
+        let (slash, name) = match rest.strip_prefix('/') {
+            Some(name) => ("/", name),
+            None => ("", rest),
+        };
+        // The name must be followed by the end of the tag or its attributes
+        let replacement = match name.as_bytes() {
+            [tag, end, ..] if *end == b'>' || end.is_ascii_whitespace() => {
+                match tag.to_ascii_lowercase() {
+                    b'b' => Some("strong"),
+                    b'i' => Some("em"),
+                    _ => None,
+                }
+            }
+            _ => None,
+        };
+        result.push('<');
+        if let Some(replacement) = replacement {
+            result.push_str(slash);
+            result.push_str(replacement);
+            rest = &name[1..];
+        }
+    }
+    result.push_str(rest);
+    result
+}
+
+/// Styles the line with its annotations, the headings and quotes are recognized by the prefixes
+/// html2text adds in front of their lines
+fn line_spans(line: &TaggedLine<Vec<RichAnnotation>>) -> Spans<'static> {
+    let mut line_style = Style::default();
+    let mut spans = Vec::new();
+    let mut in_prefix = true;
+    for element in line.iter() {
+        let string = match element {
+            TaggedLineElement::Str(string) => string,
+            _ => continue,
+        };
+        if in_prefix {
+            match block_prefix(&string.s) {
+                Some(Block::Heading) => {
+                    line_style = line_style.add_modifier(Modifier::BOLD);
+                    // The bold text replaces the `#` markers
+                    continue;
+                }
+                Some(Block::Quote) => line_style = line_style.add_modifier(Modifier::DIM),
+                Some(Block::List) => {}
+                None => in_prefix = false,
+            }
+        }
+        let style = string.tag.iter().fold(line_style, |style, annotation| {
+            style.patch(annotation_style(annotation))
+        });
+        spans.push(Span::styled(string.s.clone(), style));
+    }
+    Spans::from(spans)
+}
+
+/// Block of html2text that adds a prefix to its lines
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Block {
+    Heading,
+    Quote,
+    // Bullet, number or indentation of an item
+    List,
+}
+
+fn block_prefix(prefix: &str) -> Option<Block> {
+    let marker = prefix.strip_suffix(' ')?;
+    if marker == ">" {
+        Some(Block::Quote)
+    } else if !marker.is_empty() && marker.chars().all(|c| c == '#') {
+        Some(Block::Heading)
+    } else if marker == "*"
+        || marker.chars().all(|c| c == ' ')
+        || marker
+            .trim_end()
+            .strip_suffix('.')
+            .is_some_and(|number| number.parse::<i64>().is_ok())
+    {
+        Some(Block::List)
+    } else {
+        None
+    }
+}
+
+fn annotation_style(annotation: &RichAnnotation) -> Style {
+    match annotation {
+        RichAnnotation::Default => Style::default(),
+        RichAnnotation::Link(_) => Style::default()
+            .fg(Color::Blue)
+            .add_modifier(Modifier::UNDERLINED),
+        RichAnnotation::Image => Style::default().add_modifier(Modifier::ITALIC | Modifier::DIM),
+        RichAnnotation::Emphasis => Style::default().add_modifier(Modifier::ITALIC),
+        RichAnnotation::Strong => Style::default().add_modifier(Modifier::BOLD),
+        RichAnnotation::Strikeout => Style::default().add_modifier(Modifier::CROSSED_OUT),
+        // The terminal font is already monospace, the code is colored to stand out
+        RichAnnotation::Code | RichAnnotation::Preformat(_) => Style::default().fg(Color::Cyan),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn line_text(line: &Spans) -> String {
+        line.0.iter().map(|span| span.content.as_ref()).collect()
+    }
+
+    #[test]
+    fn test_render_html_styles() {
+        let lines = render_html(
+            "<p><strong>Bold</strong>, <em>italic</em> and <code>code</code></p>",
+            80,
+        )
+        .unwrap();
+        assert_eq!(lines.len(), 1);
+        assert_eq!(line_text(&lines[0]), "Bold, italic and code");
+        let styles: Vec<(&str, Style)> = lines[0]
+            .0
+            .iter()
+            .map(|span| (span.content.as_ref(), span.style))
+            .collect();
+        assert_eq!(
+            styles,
+            vec![
+                ("Bold", Style::default().add_modifier(Modifier::BOLD)),
+                (", ", Style::default()),
+                ("italic", Style::default().add_modifier(Modifier::ITALIC)),
+                (" and ", Style::default()),
+                ("code", Style::default().fg(Color::Cyan)),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_wrapped_rows() {
+        let rows = |text: &str, width| wrapped_rows(&Spans::from(text), width);
+        assert_eq!(rows("", 10), 1);
+        assert_eq!(rows("hello world", 11), 1);
+        assert_eq!(rows("hello world", 5), 2);
+        assert_eq!(rows("abcdefghij", 4), 3);
+        assert_eq!(rows("hello world", 0), 0);
+    }
+
+    /// Row of each line in a paragraph of tui, found with a marker drawn at the start of the line
+    fn paragraph_rows(lines: &[Spans<'static>], width: u16) -> Vec<usize> {
+        use tui::{
+            backend::TestBackend,
+            widgets::{Paragraph, Wrap},
+            Terminal,
+        };
+        (0..lines.len())
+            .map(|index| {
+                let mut text = lines[..index].to_vec();
+                text.push(Spans::from("\u{2603}"));
+                let mut terminal = Terminal::new(TestBackend::new(width, 200)).unwrap();
+                terminal
+                    .draw(|f| {
+                        let paragraph = Paragraph::new(text).wrap(Wrap { trim: false });
+                        f.render_widget(paragraph, f.size());
+                    })
+                    .unwrap();
+                let buffer = terminal.backend().buffer();
+                (0..200)
+                    .find(|row| buffer.get(0, *row).symbol == "\u{2603}")
+                    .unwrap() as usize
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_wrapped_rows_paragraph() {
+        let lines: Vec<Spans> = vec![
+            Spans::from("The lines are wrapped to the width of the view"),
+            Spans::from(""),
+            Spans::from(vec![
+                Span::styled("Styled ", Style::default().add_modifier(Modifier::BOLD)),
+                Span::raw("spans with averyveryverylongwordthatiscut and"),
+            ]),
+            Spans::from("  leading   and trailing spaces         "),
+            Spans::from("wide 日本語の文字 and\u{00a0}non breaking\u{00a0}spaces"),
+            Spans::from("end"),
+        ];
+        for width in [7, 12, 20, 33] {
+            let mut row = 0;
+            let rows: Vec<usize> = lines
+                .iter()
+                .map(|line| {
+                    let start = row;
+                    row += wrapped_rows(line, width);
+                    start
+                })
+                .collect();
+            assert_eq!(rows, paragraph_rows(&lines, width), "width {}", width);
+        }
+    }
+
+    #[test]
+    fn test_presentational_tags() {
+        assert_eq!(
+            presentational_tags("<B>Bold</B> <i class=\"x\">italic</i> <br><img src=\"i.png\">"),
+            "<strong>Bold</strong> <em class=\"x\">italic</em> <br><img src=\"i.png\">"
+        );
+        assert_eq!(presentational_tags("a < b"), "a < b");
+        assert_eq!(presentational_tags("<"), "<");
+    }
+
+    #[test]
+    fn test_render_html_blocks() {
+        let lines = render_html(
+            "<h2>Title</h2><blockquote>Quote</blockquote><ul><li>One</li><li>Two</li></ul>",
+            80,
+        )
+        .unwrap();
+        let text: Vec<String> = lines.iter().map(line_text).collect();
+        assert_eq!(text, vec!["Title", "", "> Quote", "", "* One", "* Two"]);
+        assert_eq!(
+            lines[0].0[0].style,
+            Style::default().add_modifier(Modifier::BOLD)
+        );
+        assert!(lines[2]
+            .0
+            .iter()
+            .all(|span| span.style == Style::default().add_modifier(Modifier::DIM)));
+        assert_eq!(lines[4].0[1].style, Style::default());
+    }
+
+    #[test]
+    fn test_render_html_width() {
+        let html = "<p>The lines are wrapped to the width of the view</p>";
+        let width = |lines: Vec<Spans>| lines.iter().map(Spans::width).max().unwrap();
+        assert!(width(render_html(html, 20).unwrap()) <= 20);
+        assert!(width(render_html(html, 5).unwrap()) <= MIN_WIDTH);
+        assert_eq!(render_html(html, 80).unwrap().len(), 1);
+    }
+
+    #[test]
+    fn test_render_cache() {
+        let article = Arc::new(Article {
+            id: String::from("id"),
+            source: String::from("source"),
+            content_html: String::from("<p>The lines are wrapped to the width of the view</p>"),
+            ..Default::default()
+        });
+        let mut cache = RenderCache::default();
+        let narrow = cache.get(&article, 20).clone();
+        assert_eq!(narrow, RenderedArticle::new(&article, 20));
+        assert_eq!(narrow.sub_title, None);
+        assert!(narrow.content.unwrap().len() > 1);
+        assert_eq!(cache.get(&article, 80).content.as_ref().unwrap().len(), 1);
+        // The read flag doesn't change the html
+        let read = Arc::new(Article {
+            read: true,
+            ..(*article).clone()
+        });
+        cache.get(&read, 80);
+        assert_eq!(cache.entries.len(), 2);
+        for width in 0..CACHE_SIZE as u16 {
+            cache.get(&article, 100 + width);
+        }
+        assert_eq!(cache.entries.len(), CACHE_SIZE);
+        cache.clear();
+        assert!(cache.entries.is_empty());
+    }
+
+    #[test]
+    fn test_block_prefix() {
+        assert_eq!(block_prefix("## "), Some(Block::Heading));
+        assert_eq!(block_prefix("> "), Some(Block::Quote));
+        assert_eq!(block_prefix("* "), Some(Block::List));
+        assert_eq!(block_prefix("12. "), Some(Block::List));
+        assert_eq!(block_prefix("  "), Some(Block::List));
+        assert_eq!(block_prefix("Text "), None);
+        assert_eq!(block_prefix("#hashtag"), None);
+    }
+}
@@ -0,0 +1,262 @@
+use crossterm::{style::Attribute, tty::IsTty};
+use std::{io::stdout, ops::Range};
+use tui::{
+    style::{Color, Modifier, Style},
+    text::{Span, Spans, Text},
+};
+
+use crate::{
+    configuration::Config,
+    database::{self, SNIPPET_END, SNIPPET_START},
+};
+
+/// Maximum number of results of a full-text search
+pub const SEARCH_LIMIT: i64 = 100;
+
+/// Style of the text matching the search
+pub fn match_style() -> Style {
+    Style::default()
+        .fg(Color::Black)
+        .bg(Color::Yellow)
+        .add_modifier(Modifier::BOLD)
+}
+
+/// Finds the byte ranges of the text matching the query, ignoring the case
+pub fn find_matches(text: &str, query: &str) -> Vec<Range<usize>> {
+    let query: Vec<char> = query.chars().flat_map(char::to_lowercase).collect();
+    if query.is_empty() {
+        return Vec::new();
+    }
+    // Lower case characters with the byte range of the original character they come from
+    let chars: Vec<(char, Range<usize>)> = text
+        .char_indices()
+        .flat_map(|(index, c)| {
+            let range = index..index + c.len_utf8();
+            c.to_lowercase().map(move |lower| (lower, range.clone()))
+        })
+        .collect();
+    let mut matches = Vec::new();
+    let mut i = 0;
+    while i + query.len() <= chars.len() {
+        if chars[i..i + query.len()]
+            .iter()
+            .zip(query.iter())
+            .all(|((c, _), q)| c == q)
+        {
+            matches.push(chars[i].1.start..chars[i + query.len() - 1].1.end);
+            i += query.len();
+        } else {
+            i += 1;
+        }
+    }
+    matches
+}
+
+/// Checks if the text matches the query, ignoring the case
+pub fn is_match(text: &str, query: &str) -> bool {
+    !find_matches(text, query).is_empty()
+}
+
+/// Splits a line in spans with the matches of the query highlighted
+pub fn highlight_line<'a>(line: &'a str, query: &str, style: Style) -> Spans<'a> {
+    let mut spans = Vec::new();
+    let mut last = 0;
+    for range in find_matches(line, query) {
+        if range.start > last {
+            spans.push(Span::styled(&line[last..range.start], style));
+        }
+        spans.push(Span::styled(
+            &line[range.clone()],
+            style.patch(match_style()),
+        ));
+        last = range.end;
+    }
+    if last < line.len() || spans.is_empty() {
+        spans.push(Span::styled(&line[last..], style));
+    }
+    Spans::from(spans)
+}
+
+/// Highlights the matches of the query in a styled line, keeping the style of the spans around
+/// them
+pub fn highlight_spans<'a>(line: Spans<'a>, query: &str) -> Spans<'a> {
+    let text: String = line.0.iter().map(|span| span.content.as_ref()).collect();
+    let matches = find_matches(&text, query);
+    if matches.is_empty() {
+        return line;
+    }
+    let mut spans = Vec::new();
+    let mut offset = 0;
+    for span in line.0 {
+        let end = offset + span.content.len();
+        let mut last = offset;
+        // A match can cover several spans
+        for range in matches
+            .iter()
+            .filter(|range| range.start < end && range.end > offset)
+        {
+            let (start, stop) = (range.start.max(offset), range.end.min(end));
+            if start > last {
+                spans.push(Span::styled(
+                    span.content[last - offset..start - offset].to_owned(),
+                    span.style,
+                ));
+            }
+            spans.push(Span::styled(
+                span.content[start - offset..stop - offset].to_owned(),
+                span.style.patch(match_style()),
+            ));
+            last = stop;
+        }
+        if last < end {
+            spans.push(Span::styled(
+                span.content[last - offset..].to_owned(),
+                span.style,
+            ));
+        }
+        offset = end;
+    }
+    Spans::from(spans)
+}
+
+/// Multi-line text with the matches of the query highlighted
+pub fn highlight_text<'a>(text: &'a str, query: &str, style: Style) -> Text<'a> {
+    Text::from(
+        text.lines()
+            .map(|line| highlight_line(line, query, style))
+            .collect::<Vec<Spans>>(),
+    )
+}
+
+/// Single line of the snippet of a search result with the matching terms highlighted
+pub fn highlight_snippet(snippet: &str, style: Style) -> Spans<'static> {
+    let snippet = snippet.replace('\n', " ");
+    let mut spans = Vec::new();
+    for (index, part) in snippet.split(SNIPPET_START).enumerate() {
+        // Only the parts after a start marker begin with a match
+        let (matched, rest) = match part.find(SNIPPET_END) {
+            Some(end) if index > 0 => (&part[..end], &part[end + SNIPPET_END.len()..]),
+            _ => ("", part),
+        };
+        if !matched.is_empty() {
+            spans.push(Span::styled(matched.to_owned(), style.patch(match_style())));
+        }
+        if !rest.is_empty() {
+            spans.push(Span::styled(rest.to_owned(), style));
+        }
+    }
+    Spans::from(spans)
+}
+
+/// Prints the articles in the cache matching the query, the best ones first
+pub async fn search_command(config: &Config, query: &str) -> anyhow::Result<()> {
+    let pool = database::get_database(&config.cache_path).await?;
+    let results = database::search(&pool, query, SEARCH_LIMIT).await?;
+    let feeds = database::get_feed_metadata(&pool).await?;
+    // Highlight the matches only on the terminal
+    let (bold, reset) = if stdout().is_tty() {
+        (Attribute::Bold.to_string(), Attribute::Reset.to_string())
+    } else {
+        (String::new(), String::new())
+    };
+    for result in &results {
+        // The name in the config, then the title of the feed
+        let source = config
+            .sources
+            .iter()
+            .find(|source| source.url == result.source)
+            .and_then(|source| source.name.as_ref())
+            .or_else(|| {
+                feeds
+                    .get(&result.source)
+                    .and_then(|feed| feed.title.as_ref())
+            })
+            .unwrap_or(&result.source);
+        let snippet = result
+            .snippet
+            .replace('\n', " ")
+            .replace(SNIPPET_START, &bold)
+            .replace(SNIPPET_END, &reset);
+        println!("{}\n  {}\n  {}\n", result.title, source, snippet);
+    }
+    if results.is_empty() {
+        println!("No articles match \"{}\"", query);
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_find_matches() {
+        assert_eq!(find_matches("Hello World", "o"), vec![4..5, 7..8]);
+        assert_eq!(find_matches("Hello World", "WORLD"), vec![6..11]);
+        assert_eq!(find_matches("Hello World", "xyz"), vec![]);
+        assert_eq!(find_matches("Hello World", ""), vec![]);
+        assert_eq!(find_matches("aaaa", "aa"), vec![0..2, 2..4]);
+    }
+
+    #[test]
+    fn test_find_matches_unicode() {
+        // The byte ranges are the ones of the original text
+        assert_eq!(find_matches("Ünïcode ÜNÏ", "ünï"), vec![0..5, 10..15]);
+        // İ is lower cased in two characters
+        assert_eq!(find_matches("İstanbul", "stan"), vec![2..6]);
+    }
+
+    #[test]
+    fn test_highlight_line() {
+        let spans = highlight_line("Hello World", "world", Style::default());
+        assert_eq!(
+            spans,
+            Spans::from(vec![
+                Span::raw("Hello "),
+                Span::styled("World", match_style())
+            ])
+        );
+        let spans = highlight_line("", "world", Style::default());
+        assert_eq!(spans, Spans::from(vec![Span::raw("")]));
+    }
+
+    #[test]
+    fn test_highlight_spans() {
+        let bold = Style::default().add_modifier(Modifier::BOLD);
+        let line = Spans::from(vec![Span::styled("Hello ", bold), Span::raw("World")]);
+        let spans = highlight_spans(line, "o w");
+        let spans: Vec<(&str, Style)> = spans
+            .0
+            .iter()
+            .map(|span| (span.content.as_ref(), span.style))
+            .collect();
+        assert_eq!(
+            spans,
+            vec![
+                ("Hell", bold),
+                ("o ", bold.patch(match_style())),
+                ("W", match_style()),
+                ("orld", Style::default()),
+            ]
+        );
+        let line = Spans::from("no match");
+        assert_eq!(highlight_spans(line.clone(), "query"), line);
+    }
+
+    #[test]
+    fn test_highlight_snippet() {
+        let snippet = format!(
+            "...a {}feed{} reader\nin {}Rust{}",
+            SNIPPET_START, SNIPPET_END, SNIPPET_START, SNIPPET_END
+        );
+        assert_eq!(
+            highlight_snippet(&snippet, Style::default()),
+            Spans::from(vec![
+                Span::raw("...a "),
+                Span::styled("feed", match_style()),
+                Span::raw(" reader in "),
+                Span::styled("Rust", match_style()),
+            ])
+        );
+    }
+}
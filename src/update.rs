@@ -1,92 +1,1192 @@
+use chrono::{DateTime, Datelike, Timelike, Utc};
+use reqwest::{
+    header::{
+        HeaderMap, CACHE_CONTROL, CONTENT_TYPE, ETAG, EXPIRES, IF_MODIFIED_SINCE, IF_NONE_MATCH,
+        LAST_MODIFIED, RETRY_AFTER,
+    },
+    Client, StatusCode, Url,
+};
 use sqlx::SqlitePool;
 use std::{
-    collections::{HashMap},
+    collections::{
+        hash_map::{HashMap, RandomState},
+        HashSet,
+    },
+    error::Error,
+    fmt,
+    hash::{BuildHasher, Hasher},
     sync::{Arc, RwLock},
 };
 use tokio::{
-    sync::mpsc,
-    time::{interval, Duration},
+    sync::{mpsc, Semaphore},
+    time::{sleep_until, Instant},
 };
 
 use crate::{
-    configuration::Config,
-    content::{parse_content, Article, ArticleMap},
+    configuration::{Config, Source},
+    content::{
+        feed_links, parse_content, Article, ArticleMap, FeedMetadata, ParsedFeed, UpdateHints,
+    },
+    database::{self, Validators},
 };
 
-async fn request_content(url: &str) -> reqwest::Result<String> {
-    // TODO: Check status and show errors
-    reqwest::get(url).await?.text().await
+/// Delay in seconds before retrying a failed source, doubled at each consecutive failure
+const RETRY_BASE: u64 = 30;
+/// Maximum delay in seconds before retrying a failed source
+const RETRY_MAX: u64 = 6 * 60 * 60;
+/// Maximum delay in seconds between the updates requested by the publisher of a feed
+const MAX_HINT: u64 = 24 * 60 * 60;
+/// Maximum number of concurrent requests to the same host
+const MAX_HOST_REQUESTS: usize = 2;
+
+/// Kind of error of a failed update of a source
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ErrorKind {
+    Dns,
+    Tls,
+    Connect,
+    Timeout,
+    // Response with an error status code
+    Http(u16),
+    // The content is not a valid feed
+    Parse,
+    Other,
+}
+
+impl ErrorKind {
+    /// Code of the kind stored in the database
+    pub fn code(&self) -> String {
+        match self {
+            ErrorKind::Dns => String::from("dns"),
+            ErrorKind::Tls => String::from("tls"),
+            ErrorKind::Connect => String::from("connect"),
+            ErrorKind::Timeout => String::from("timeout"),
+            ErrorKind::Http(status) => format!("http {}", status),
+            ErrorKind::Parse => String::from("parse"),
+            ErrorKind::Other => String::from("other"),
+        }
+    }
+
+    /// Kind from the code stored in the database
+    pub fn from_code(code: &str) -> ErrorKind {
+        match code {
+            "dns" => ErrorKind::Dns,
+            "tls" => ErrorKind::Tls,
+            "connect" => ErrorKind::Connect,
+            "timeout" => ErrorKind::Timeout,
+            "parse" => ErrorKind::Parse,
+            _ => code
+                .strip_prefix("http ")
+                .and_then(|status| status.parse().ok())
+                .map_or(ErrorKind::Other, ErrorKind::Http),
+        }
+    }
+}
+
+impl fmt::Display for ErrorKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ErrorKind::Dns => write!(f, "DNS error"),
+            ErrorKind::Tls => write!(f, "TLS error"),
+            ErrorKind::Connect => write!(f, "Connection error"),
+            ErrorKind::Timeout => write!(f, "Timeout"),
+            ErrorKind::Http(status) => write!(f, "HTTP {}", status),
+            ErrorKind::Parse => write!(f, "Parse error"),
+            ErrorKind::Other => write!(f, "Error"),
+        }
+    }
+}
+
+/// Error of a failed update of a source
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct FetchError {
+    pub kind: ErrorKind,
+    pub message: String,
+    // Delay requested by the server before the next request
+    pub retry_after: Option<chrono::Duration>,
+}
+
+impl From<reqwest::Error> for FetchError {
+    fn from(err: reqwest::Error) -> Self {
+        // The errors of the connection are only distinguishable by the message of their source
+        let mut messages = Vec::new();
+        let mut source = err.source();
+        while let Some(err) = source {
+            messages.push(err.to_string().to_lowercase());
+            source = err.source();
+        }
+        let contains = |patterns: &[&str]| {
+            messages
+                .iter()
+                .any(|x| patterns.iter().any(|p| x.contains(p)))
+        };
+        let kind = if let Some(status) = err.status() {
+            ErrorKind::Http(status.as_u16())
+        } else if err.is_timeout() {
+            ErrorKind::Timeout
+        } else if contains(&["dns error"]) {
+            ErrorKind::Dns
+        } else if contains(&["tls", "ssl", "certificate"]) {
+            ErrorKind::Tls
+        } else if err.is_connect() {
+            ErrorKind::Connect
+        } else {
+            ErrorKind::Other
+        };
+        FetchError {
+            kind,
+            message: err.to_string(),
+            retry_after: None,
+        }
+    }
+}
+
+/// Parses the value of the Retry-After header, either the seconds to wait or the date of the next
+/// request, capped at the maximum delay of the retries
+fn parse_retry_after(value: &str, now: DateTime<Utc>) -> Option<chrono::Duration> {
+    let value = value.trim();
+    let max = chrono::Duration::seconds(RETRY_MAX as i64);
+    if let Ok(seconds) = value.parse::<u32>() {
+        return Some(chrono::Duration::seconds(seconds.into()).min(max));
+    }
+    let date = DateTime::parse_from_rfc2822(value).ok()?;
+    Some(
+        (date.with_timezone(&Utc) - now)
+            .max(chrono::Duration::zero())
+            .min(max),
+    )
+}
+
+/// Delay before retrying a source after the consecutive failures. The delay grows exponentially up
+/// to a maximum, and it's randomized so the retries of the sources failed together are spread out.
+fn backoff(failures: u32) -> chrono::Duration {
+    let exponent = failures.saturating_sub(1).min(16);
+    let delay = RETRY_BASE.saturating_mul(1 << exponent).min(RETRY_MAX);
+    // Each RandomState has different keys, so the hash is a random number
+    let random = RandomState::new().build_hasher().finish();
+    // Between half and one and a half times the delay
+    let jitter = 0.5 + (random % 1000) as f64 / 1000.0;
+    chrono::Duration::seconds((delay as f64 * jitter) as i64)
+}
+
+/// State of the updates of a source
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct FetchStatus {
+    pub last_success: Option<DateTime<Utc>>,
+    pub last_failure: Option<DateTime<Utc>>,
+    // Error of the last update, cleared by a successful one
+    pub error: Option<FetchError>,
+    pub failures: u32,
+    // Time of the next retry after a failed update
+    pub next_retry: Option<DateTime<Utc>>,
+    // Time the source is due after the last successful update
+    pub next_update: Option<DateTime<Utc>>,
+}
+
+impl FetchStatus {
+    pub fn succeeded(&mut self, time: DateTime<Utc>, next_update: DateTime<Utc>) {
+        self.last_success = Some(time);
+        self.next_update = Some(next_update);
+        self.error = None;
+        self.failures = 0;
+        self.next_retry = None;
+    }
+
+    /// Records the error and schedules the retry, after the delay requested by the server if any
+    pub fn failed(&mut self, error: FetchError, time: DateTime<Utc>) {
+        self.last_failure = Some(time);
+        self.failures = self.failures.saturating_add(1);
+        let delay = error.retry_after.unwrap_or_else(|| backoff(self.failures));
+        self.next_retry = Some(time + delay);
+        self.error = Some(error);
+    }
+
+    /// Time the source is due, the retry if the last update failed
+    pub fn due(&self) -> Option<DateTime<Utc>> {
+        self.next_retry.or(self.next_update)
+    }
+}
+
+/// Time of the next update of a source. The feed is not requested before the configured interval,
+/// the time to live or update period of the feed and the expiration of the response, then the
+/// update is delayed out of the hours and days skipped by the publisher.
+fn next_update(
+    now: DateTime<Utc>,
+    interval: u64,
+    hints: &UpdateHints,
+    expires: Option<DateTime<Utc>>,
+) -> DateTime<Utc> {
+    let freshness = expires.map(|expires| (expires - now).num_seconds().max(0) as u64);
+    // The hints are limited, so a wrong one doesn't stop the updates
+    let delay = [hints.ttl, hints.update_period, freshness]
+        .iter()
+        .flatten()
+        .map(|hint| (*hint).min(MAX_HINT))
+        .fold(interval, u64::max);
+    let mut next = now + chrono::Duration::seconds(delay as i64);
+    // Moves to the start of the next hour until the hour is not skipped, for at most a week
+    for _ in 0..(7 * 24) {
+        if !hints.skip_hours.contains(&next.hour()) && !hints.skip_days.contains(&next.weekday()) {
+            return next;
+        }
+        next = next - chrono::Duration::seconds(i64::from(next.minute() * 60 + next.second()))
+            + chrono::Duration::hours(1);
+    }
+    // Every hour is skipped, so the hints are ignored
+    now + chrono::Duration::seconds(interval as i64)
+}
+
+/// Time until the response can be cached, from the Cache-Control or Expires headers
+fn expires(headers: &HeaderMap, now: DateTime<Utc>) -> Option<DateTime<Utc>> {
+    let header = |name| headers.get(name).and_then(|value| value.to_str().ok());
+    if let Some(cache_control) = header(CACHE_CONTROL) {
+        let mut max_age = None;
+        for directive in cache_control.split(',').map(str::trim) {
+            if directive == "no-cache" || directive == "no-store" {
+                return None;
+            }
+            if let Some(seconds) = directive.strip_prefix("max-age=") {
+                max_age = seconds.trim_matches('"').parse::<u32>().ok();
+            }
+        }
+        // The max age has precedence over the Expires header
+        if let Some(max_age) = max_age {
+            return Some(now + chrono::Duration::seconds(max_age.into()));
+        }
+    }
+    let expires = DateTime::parse_from_rfc2822(header(EXPIRES)?).ok()?;
+    Some(expires.with_timezone(&Utc))
+}
+
+/// Response to the request of a feed, with the time until it can be cached
+#[derive(Debug, PartialEq)]
+enum Response {
+    // New content of the feed and its content type, with the validators to send in the next
+    // request
+    Modified(String, Option<String>, Validators, Option<DateTime<Utc>>),
+    // The feed didn't change since the last request
+    NotModified(Option<DateTime<Utc>>),
+}
+
+/// Requests the feed of the source. The validators of the last response are sent, so the feed is
+/// downloaded only if changed.
+async fn request_content(
+    client: &Client,
+    source: &Source,
+    validators: &Validators,
+) -> Result<Response, FetchError> {
+    let mut request = client.get(&source.url);
+    for (name, value) in &source.headers {
+        request = request.header(name.as_str(), value.as_str());
+    }
+    if let Some(etag) = &validators.etag {
+        request = request.header(IF_NONE_MATCH, etag.as_str());
+    }
+    if let Some(last_modified) = &validators.last_modified {
+        request = request.header(IF_MODIFIED_SINCE, last_modified.as_str());
+    }
+    let response = request.send().await?;
+    let expires = expires(response.headers(), Utc::now());
+    if response.status() == StatusCode::NOT_MODIFIED {
+        return Ok(Response::NotModified(expires));
+    }
+    if let Err(err) = response.error_for_status_ref() {
+        let mut error = FetchError::from(err);
+        let status = response.status();
+        if status == StatusCode::TOO_MANY_REQUESTS || status == StatusCode::SERVICE_UNAVAILABLE {
+            error.retry_after = response
+                .headers()
+                .get(RETRY_AFTER)
+                .and_then(|value| value.to_str().ok())
+                .and_then(|value| parse_retry_after(value, Utc::now()));
+        }
+        return Err(error);
+    }
+    let header = |name| {
+        response
+            .headers()
+            .get(name)
+            .and_then(|value| value.to_str().ok())
+            .map(String::from)
+    };
+    let validators = Validators {
+        etag: header(ETAG),
+        last_modified: header(LAST_MODIFIED),
+    };
+    let content_type = header(CONTENT_TYPE);
+    Ok(Response::Modified(
+        response.text().await?,
+        content_type,
+        validators,
+        expires,
+    ))
+}
+
+/// Url of the feed of a page: the url itself if it's a feed, otherwise the first feed the page
+/// links as alternate
+pub async fn find_feed(client: &Client, url: &str) -> anyhow::Result<String> {
+    let fetch = |url: String| async move {
+        match request_content(client, &Source::new(url.clone()), &Validators::default()).await {
+            Ok(Response::Modified(content, content_type, _, _)) => Ok((content, content_type)),
+            Ok(Response::NotModified(_)) => Err(anyhow::anyhow!("{}: no content", url)),
+            Err(err) => Err(anyhow::anyhow!("{}: {}", url, err.message)),
+        }
+    };
+    let (content, content_type) = fetch(String::from(url)).await?;
+    if parse_content(url, content_type.as_deref(), content.clone()).is_ok() {
+        return Ok(String::from(url));
+    }
+    for link in feed_links(url, &content) {
+        if let Ok((content, content_type)) = fetch(link.clone()).await {
+            if parse_content(&link, content_type.as_deref(), content).is_ok() {
+                return Ok(link);
+            }
+        }
+    }
+    Err(anyhow::anyhow!("{} is not a feed", url))
+}
+
+/// Result of a successful update of a source
+struct Fetched {
+    // New validators and content, None if the feed is not modified
+    feed: Option<(Validators, ParsedFeed)>,
+    // Time the source is due again
+    next_update: DateTime<Utc>,
+}
+
+/// Requests and parses the feed of the source, then schedules the next update
+async fn fetch_source(
+    client: &Client,
+    pool: &SqlitePool,
+    source: &Source,
+    update_interval: u64,
+) -> Result<Fetched, FetchError> {
+    // Without the validators the whole feed is requested
+    let validators = database::get_validators(pool, &source.url)
+        .await
+        .unwrap_or_default();
+    let interval = source.update_interval.unwrap_or(update_interval);
+    match request_content(client, source, &validators).await? {
+        Response::Modified(body, content_type, validators, expires) => {
+            let feed =
+                parse_content(&source.url, content_type.as_deref(), body).map_err(|err| {
+                    FetchError {
+                        kind: ErrorKind::Parse,
+                        message: err.to_string(),
+                        retry_after: None,
+                    }
+                })?;
+            Ok(Fetched {
+                next_update: next_update(Utc::now(), interval, &feed.hints, expires),
+                feed: Some((validators, feed)),
+            })
+        }
+        Response::NotModified(expires) => {
+            // The hints of the last version of the feed are still valid
+            let hints = database::get_update_hints(pool, &source.url)
+                .await
+                .unwrap_or_default();
+            Ok(Fetched {
+                next_update: next_update(Utc::now(), interval, &hints, expires),
+                feed: None,
+            })
+        }
+    }
 }
 
-/// Asynchronously retrieves the content from the sources in the config
-async fn get_content(sources: &[Arc<String>]) -> HashMap<(String, String), Article> {
-    // Set of the new articles
-    let mut result: HashMap<(String, String), Article> = HashMap::new();
-    // Channel for retrieving the parsed articles
-    let (sender, mut receiver) = mpsc::channel::<Article>(sources.len());
-    // Spawns update threads
-    sources.iter().for_each(|source| {
+/// Limits the concurrent requests to the same host
+#[derive(Default)]
+struct HostLimits {
+    hosts: HashMap<String, Arc<Semaphore>>,
+}
+
+impl HostLimits {
+    /// Returns the semaphore of the host of the url, None if the url is invalid
+    fn get(&mut self, url: &str) -> Option<Arc<Semaphore>> {
+        let host = Url::parse(url).ok()?.host_str()?.to_owned();
+        let semaphore = self
+            .hosts
+            .entry(host)
+            .or_insert_with(|| Arc::new(Semaphore::new(MAX_HOST_REQUESTS)));
+        Some(Arc::clone(semaphore))
+    }
+}
+
+/// Request to fetch the sources before they are due
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Refresh {
+    Source(String),
+    All,
+    // A source added while running, it's fetched immediately
+    Subscribe(Arc<Source>),
+}
+
+/// Result of the fetch of a source sent back to the scheduler
+type FetchResult = (String, Result<Fetched, FetchError>);
+
+/// Fetches each source independently when it's due
+struct Scheduler {
+    client: Client,
+    pool: Arc<SqlitePool>,
+    hosts: HostLimits,
+    update_interval: u64,
+    // Channel of the fetched sources
+    sender: mpsc::UnboundedSender<FetchResult>,
+    // Time of the next update of the sources not being fetched
+    next_update: HashMap<String, Instant>,
+    // Urls of the sources being fetched
+    fetching: Arc<RwLock<HashSet<String>>>,
+}
+
+impl Scheduler {
+    /// Returns the time of the first source due
+    fn next_due(&self) -> Option<Instant> {
+        self.next_update.values().min().copied()
+    }
+
+    /// Starts fetching the due sources, the result is sent on the channel
+    fn fetch_due(&mut self, sources: &[Arc<Source>]) {
+        let now = Instant::now();
+        for source in sources {
+            if self
+                .next_update
+                .get(&source.url)
+                .is_some_and(|next| *next <= now)
+            {
+                self.next_update.remove(&source.url);
+                self.fetch(source);
+            }
+        }
+    }
+
+    /// Fetches the requested sources now, unless they are already being fetched
+    fn refresh(&mut self, sources: &[Arc<Source>], refresh: &Refresh) {
+        for source in sources {
+            let requested = match refresh {
+                Refresh::Source(url) => source.url == *url,
+                Refresh::All => true,
+                Refresh::Subscribe(new) => source.url == new.url,
+            };
+            if requested && self.next_update.remove(&source.url).is_some() {
+                self.fetch(source);
+            }
+        }
+    }
+
+    /// Adds the source to the ones updated, it's due immediately
+    fn subscribe(&mut self, sources: &mut Vec<Arc<Source>>, source: &Arc<Source>) {
+        if source.enabled && !sources.iter().any(|other| other.url == source.url) {
+            sources.push(Arc::clone(source));
+            self.next_update.insert(source.url.clone(), Instant::now());
+        }
+    }
+
+    /// Fetches the source in its own task
+    fn fetch(&mut self, source: &Arc<Source>) {
+        self.fetching.write().unwrap().insert(source.url.clone());
         let source = Arc::clone(source);
-        let sender = sender.clone();
+        let client = self.client.clone();
+        let pool = Arc::clone(&self.pool);
+        let host = self.hosts.get(&source.url);
+        let update_interval = self.update_interval;
+        let sender = self.sender.clone();
         tokio::spawn(async move {
-            let articles = parse_content(&source, request_content(&source).await.unwrap()).unwrap();
-            for article in articles {
-                sender.send(article).await.unwrap();
-            }
+            // Waits for the other requests to the same host, the semaphore is never closed
+            let _permit = match &host {
+                Some(host) => Some(host.acquire().await.unwrap()),
+                None => None,
+            };
+            let fetched = fetch_source(&client, &pool, &source, update_interval).await;
+            // The receiver lives as long as the scheduler
+            let _ = sender.send((source.url.clone(), fetched));
         });
-    });
-    drop(sender);
-    // Waits to receive the result for each thread
-    while let Some(res) = receiver.recv().await {
-        result.insert((res.id.clone(), res.source.clone()), res);
     }
-    result
 }
 
-pub fn update_thread(config: &Config, pool: &Arc<SqlitePool>, content: &Arc<RwLock<ArticleMap>>) {
-    if !config.sources.is_empty() {
-        let update_interval = config.update_interval;
-        let sources: Vec<Arc<String>> = config.sources.iter().map(|x| Arc::clone(x)).collect();
-        let content_c = Arc::clone(content);
-        let pool = Arc::clone(pool);
+/// Update of a single source, merged in the content as soon as the source is fetched
+struct SourceUpdate {
+    url: String,
+    articles: Vec<Article>,
+    // Validators, hints and information of the feed, None if not modified
+    feed: Option<(Validators, UpdateHints, FeedMetadata)>,
+    status: FetchStatus,
+}
 
-        tokio::spawn(async move {
-            let mut interval = interval(Duration::from_secs(update_interval));
-            loop {
-                interval.tick().await;
-                let content_update = get_content(&sources).await;
-                let deleted_content;
-                {
-                    let mut content = content_c.write().unwrap();
-                    deleted_content = content.update_content(&content_update);
+/// Records the result of the fetch in the status of the source and returns the update. The failed
+/// or not modified sources have no articles in the update, so their articles are kept.
+fn source_update(
+    (url, fetched): FetchResult,
+    status: &RwLock<HashMap<String, FetchStatus>>,
+) -> SourceUpdate {
+    let mut status = status.write().unwrap();
+    let source_status = status.entry(url.clone()).or_default();
+    let mut articles = Vec::new();
+    let mut feed = None;
+    match fetched {
+        Ok(fetched) => {
+            source_status.succeeded(Utc::now(), fetched.next_update);
+            if let Some((validators, parsed)) = fetched.feed {
+                articles = parsed.articles;
+                feed = Some((validators, parsed.hints, parsed.metadata));
+            }
+        }
+        Err(err) => source_status.failed(err, Utc::now()),
+    }
+    SourceUpdate {
+        url,
+        articles,
+        feed,
+        status: source_status.clone(),
+    }
+}
+
+/// Starts the task updating the sources when they are due, returns the channel to refresh them
+/// on request
+pub fn update_thread(
+    config: &Config,
+    pool: &Arc<SqlitePool>,
+    content: &Arc<RwLock<ArticleMap>>,
+    status: &Arc<RwLock<HashMap<String, FetchStatus>>>,
+    fetching: &Arc<RwLock<HashSet<String>>>,
+    feeds: &Arc<RwLock<HashMap<String, FeedMetadata>>>,
+) -> mpsc::UnboundedSender<Refresh> {
+    let (refresh_sender, mut refresh_receiver) = mpsc::unbounded_channel();
+    let mut sources: Vec<Arc<Source>> = config
+        .sources
+        .iter()
+        .filter(|source| source.enabled)
+        .map(Arc::clone)
+        .collect();
+    let retention = config.retention;
+    let content = Arc::clone(content);
+    let status = Arc::clone(status);
+    let feeds = Arc::clone(feeds);
+    let (sender, mut receiver) = mpsc::unbounded_channel();
+    let mut scheduler = Scheduler {
+        client: Client::new(),
+        pool: Arc::clone(pool),
+        hosts: HostLimits::default(),
+        update_interval: config.update_interval,
+        sender,
+        // The failed sources wait for their retry
+        next_update: {
+            let status = status.read().unwrap();
+            sources
+                .iter()
+                .map(|source| {
+                    let due = status.get(&source.url).and_then(FetchStatus::due);
+                    (source.url.clone(), instant_at(due))
+                })
+                .collect()
+        },
+        fetching: Arc::clone(fetching),
+    };
+
+    tokio::spawn(async move {
+        loop {
+            let next_due = scheduler.next_due();
+            tokio::select! {
+                _ = sleep_until_due(next_due) => scheduler.fetch_due(&sources),
+                Some(refresh) = refresh_receiver.recv() => {
+                    if let Refresh::Subscribe(source) = &refresh {
+                        scheduler.subscribe(&mut sources, source);
+                    }
+                    scheduler.refresh(&sources, &refresh);
+                }
+                Some(fetched) = receiver.recv() => {
+                    let mut update = source_update(fetched, &status);
+                    // Scheduled by the publisher hints, or by the backoff if failed
+                    scheduler
+                        .next_update
+                        .insert(update.url.clone(), instant_at(update.status.due()));
+                    // Skip the articles still in the feed that the cleanup would delete again
+                    update
+                        .articles
+                        .retain(|article| !retention.is_expired(article.date));
+                    // The title of the feed is in place when the content changes
+                    if let Some((_, _, metadata)) = &update.feed {
+                        feeds
+                            .write()
+                            .unwrap()
+                            .insert(update.url.clone(), metadata.clone());
+                    }
+                    content
+                        .write()
+                        .unwrap()
+                        .update_source(&update.url, &update.articles);
+                    scheduler.fetching.write().unwrap().remove(&update.url);
+                    update_cache(&scheduler.pool, &status, update);
                 }
-                update_cache(&pool, content_update.values().cloned().collect(), deleted_content.keys().cloned().collect());
             }
-        });
+        }
+    });
+    refresh_sender
+}
+
+/// Sleeps until the time, forever if there is none
+async fn sleep_until_due(time: Option<Instant>) {
+    match time {
+        Some(time) => sleep_until(time).await,
+        None => std::future::pending().await,
     }
 }
 
-/// It will invalidate every element in the database and then insert the new content with the new
-/// data. Then delete the content not found in the update.
-// TODO: This can be improved by deleting only the content with a time stamp or inserted some time
-// ago.
-pub fn update_cache(pool: &Arc<SqlitePool>, content_update: Vec<Article>, deleted_content: Vec<(String, String)>) {
+/// Converts the time to an instant, the past or missing times are now
+fn instant_at(time: Option<DateTime<Utc>>) -> Instant {
+    let delay = time
+        .and_then(|time| (time - Utc::now()).to_std().ok())
+        .unwrap_or_default();
+    Instant::now() + delay
+}
+
+/// Inserts the new content in the database and updates the already present one. The old content
+/// is deleted by the cleanup task in the database module. The validators are stored after the
+/// articles, so a feed is never skipped before its articles are saved. A failure is recorded in
+/// the status of the source, the validators are not stored so the next update saves it again.
+fn update_cache(
+    pool: &Arc<SqlitePool>,
+    status: &Arc<RwLock<HashMap<String, FetchStatus>>>,
+    update: SourceUpdate,
+) {
     let pool = Arc::clone(pool);
+    let status = Arc::clone(status);
     tokio::spawn(async move {
-        crate::database::insert_articles(&pool, &content_update).await.unwrap();
-        crate::database::delete_articles(&pool, &deleted_content).await.unwrap();
+        let url = update.url.clone();
+        if let Err(err) = save_update(&pool, update).await {
+            status.write().unwrap().entry(url).or_default().failed(
+                FetchError {
+                    kind: ErrorKind::Other,
+                    message: format!("failed to save the update: {}", err),
+                    retry_after: None,
+                },
+                Utc::now(),
+            );
+        }
     });
 }
 
+/// Stores the update in the database, in the order described in `update_cache`
+async fn save_update(pool: &SqlitePool, update: SourceUpdate) -> sqlx::Result<()> {
+    database::insert_articles(pool, &update.articles).await?;
+    if let Some((validators, hints, metadata)) = update.feed {
+        database::set_feed_metadata(pool, &[(update.url.clone(), metadata)]).await?;
+        database::set_validators(pool, &[(update.url.clone(), validators)]).await?;
+        database::set_update_hints(pool, &[(update.url.clone(), hints)]).await?;
+    }
+    database::set_fetch_status(pool, &[(update.url, update.status)]).await
+}
+
 #[cfg(test)]
 mod test {
 
-    use super::request_content;
+    use super::*;
+    use chrono::Weekday;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use tokio::{
+        io::{AsyncReadExt, AsyncWriteExt},
+        net::{TcpListener, TcpStream},
+        time::{sleep, Duration},
+    };
 
     #[tokio::test]
     async fn test_request_content() {
-        request_content("https://joshuachp.github.io/index.xml")
+        let source = Source::new(String::from("https://joshuachp.github.io/index.xml"));
+        request_content(&Client::new(), &source, &Validators::default())
             .await
             .unwrap();
     }
+
+    /// Reads the head of a request, which can arrive in several reads
+    async fn read_request(stream: &mut TcpStream) -> String {
+        let mut request = Vec::new();
+        while !request.windows(4).any(|end| end == b"\r\n\r\n") {
+            let mut buf = [0; 1024];
+            let len = stream.read(&mut buf).await.unwrap();
+            if len == 0 {
+                break;
+            }
+            request.extend_from_slice(&buf[..len]);
+        }
+        String::from_utf8_lossy(&request).into_owned()
+    }
+
+    /// Serves a feed with an ETag, answering Not Modified when the request has the same ETag
+    async fn serve_conditional(listener: TcpListener) {
+        loop {
+            let (mut stream, _) = listener.accept().await.unwrap();
+            let request = read_request(&mut stream).await.to_lowercase();
+            let response = if request.contains("if-none-match: \"v1\"") {
+                "HTTP/1.1 304 Not Modified\r\nETag: \"v1\"\r\nConnection: close\r\n\r\n".to_owned()
+            } else {
+                let body = "feed";
+                format!(
+                    "HTTP/1.1 200 OK\r\nETag: \"v1\"\r\n\
+                    Last-Modified: Wed, 21 Oct 2015 07:28:00 GMT\r\n\
+                    Content-Length: {}\r\nConnection: close\r\n\r\n{}",
+                    body.len(),
+                    body
+                )
+            };
+            stream.write_all(response.as_bytes()).await.unwrap();
+        }
+    }
+
+    /// Serves an html page linking its feeds, the first one is missing
+    async fn serve_page(listener: TcpListener) {
+        loop {
+            let (mut stream, _) = listener.accept().await.unwrap();
+            let request = read_request(&mut stream).await;
+            let (status, body) = if request.starts_with("GET /page ") {
+                (
+                    "200 OK",
+                    "<html><head>\
+                    <link rel=\"alternate\" type=\"application/rss+xml\" href=\"/missing.xml\">\
+                    <link rel=\"alternate\" type=\"application/atom+xml\" href=\"/feed.xml\">\
+                    </head><body><a href=\"/feed.xml\">Feed</a></body></html>"
+                        .to_owned(),
+                )
+            } else if request.starts_with("GET /feed.xml ") {
+                (
+                    "200 OK",
+                    std::fs::read_to_string("tests/feeds/atom.xml").unwrap(),
+                )
+            } else {
+                ("404 Not Found", String::new())
+            };
+            let response = format!(
+                "HTTP/1.1 {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+                status,
+                body.len(),
+                body
+            );
+            stream.write_all(response.as_bytes()).await.unwrap();
+        }
+    }
+
+    #[tokio::test]
+    async fn test_find_feed() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap();
+        tokio::spawn(serve_page(listener));
+        let client = Client::new();
+        let feed = format!("http://{}/feed.xml", address);
+        assert_eq!(find_feed(&client, &feed).await.unwrap(), feed);
+        let page = format!("http://{}/page", address);
+        assert_eq!(find_feed(&client, &page).await.unwrap(), feed);
+        let missing = format!("http://{}/missing.xml", address);
+        find_feed(&client, &missing).await.unwrap_err();
+    }
+
+    /// Answers Service Unavailable after a while, counting the maximum of concurrent requests
+    async fn serve_unavailable(listener: TcpListener, max_active: Arc<AtomicUsize>) {
+        let active = Arc::new(AtomicUsize::new(0));
+        loop {
+            let (mut stream, _) = listener.accept().await.unwrap();
+            let active = Arc::clone(&active);
+            let max_active = Arc::clone(&max_active);
+            tokio::spawn(async move {
+                let current = active.fetch_add(1, Ordering::SeqCst) + 1;
+                max_active.fetch_max(current, Ordering::SeqCst);
+                read_request(&mut stream).await;
+                sleep(Duration::from_millis(100)).await;
+                active.fetch_sub(1, Ordering::SeqCst);
+                let response = "HTTP/1.1 503 Service Unavailable\r\nRetry-After: 120\r\n\
+                    Content-Length: 0\r\nConnection: close\r\n\r\n";
+                stream.write_all(response.as_bytes()).await.unwrap();
+            });
+        }
+    }
+
+    #[tokio::test]
+    async fn test_fetch_error_kind() {
+        let client = Client::new();
+        // Nothing listens on the discard port
+        let source = Source::new(String::from("http://127.0.0.1:9/feed.xml"));
+        let err = request_content(&client, &source, &Validators::default())
+            .await
+            .unwrap_err();
+        assert_eq!(err.kind, ErrorKind::Connect);
+        let source = Source::new(String::from("http://feedrs.invalid/feed.xml"));
+        let err = request_content(&client, &source, &Validators::default())
+            .await
+            .unwrap_err();
+        assert_eq!(err.kind, ErrorKind::Dns);
+    }
+
+    #[tokio::test]
+    async fn test_fetch_unavailable() {
+        let path = std::env::temp_dir().join("feedrs_fetch_unavailable.db");
+        if path.exists() {
+            std::fs::remove_file(&path).unwrap();
+        }
+        let pool = Arc::new(database::get_database(&path).await.unwrap());
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap();
+        let max_active = Arc::new(AtomicUsize::new(0));
+        tokio::spawn(serve_unavailable(listener, Arc::clone(&max_active)));
+        let status = RwLock::new(HashMap::new());
+        let sources: Vec<Arc<Source>> = (0..5)
+            .map(|i| Arc::new(Source::new(format!("http://{}/{}.xml", address, i))))
+            .collect();
+        let (sender, mut receiver) = mpsc::unbounded_channel();
+        let mut scheduler = Scheduler {
+            client: Client::new(),
+            pool,
+            hosts: HostLimits::default(),
+            update_interval: 300,
+            sender,
+            next_update: sources
+                .iter()
+                .map(|source| (source.url.clone(), Instant::now()))
+                .collect(),
+            fetching: Arc::default(),
+        };
+        let start = Utc::now();
+        scheduler.fetch_due(&sources);
+        // The sources being fetched are not scheduled
+        assert_eq!(scheduler.next_due(), None);
+        assert_eq!(scheduler.fetching.read().unwrap().len(), sources.len());
+        let mut updates = Vec::new();
+        for _ in 0..sources.len() {
+            updates.push(source_update(receiver.recv().await.unwrap(), &status));
+        }
+        // The requests to the same host are limited
+        assert_eq!(max_active.load(Ordering::SeqCst), MAX_HOST_REQUESTS);
+        for update in &updates {
+            let source_status = &update.status;
+            let error = source_status.error.as_ref().unwrap();
+            assert_eq!(error.kind, ErrorKind::Http(503));
+            assert_eq!(error.retry_after, Some(chrono::Duration::seconds(120)));
+            // The retry is after the requested delay instead of the backoff
+            let retry = source_status.next_retry.unwrap() - start;
+            assert!(retry >= chrono::Duration::seconds(120));
+            assert!(retry < chrono::Duration::seconds(130));
+        }
+    }
+
+    #[test]
+    fn test_parse_retry_after() {
+        let now = DateTime::parse_from_rfc3339("2015-10-21T07:28:00+00:00")
+            .unwrap()
+            .with_timezone(&Utc);
+        assert_eq!(
+            parse_retry_after("120", now),
+            Some(chrono::Duration::seconds(120))
+        );
+        assert_eq!(
+            parse_retry_after("Wed, 21 Oct 2015 07:30:00 GMT", now),
+            Some(chrono::Duration::seconds(120))
+        );
+        // A date in the past means now
+        assert_eq!(
+            parse_retry_after("Wed, 21 Oct 2015 07:00:00 GMT", now),
+            Some(chrono::Duration::zero())
+        );
+        // The delay is capped like the backoff
+        assert_eq!(
+            parse_retry_after("31536000", now),
+            Some(chrono::Duration::seconds(RETRY_MAX as i64))
+        );
+        assert_eq!(
+            parse_retry_after("Fri, 21 Oct 2016 07:30:00 GMT", now),
+            Some(chrono::Duration::seconds(RETRY_MAX as i64))
+        );
+        assert_eq!(parse_retry_after("soon", now), None);
+        assert_eq!(parse_retry_after("-1", now), None);
+    }
+
+    fn time(time: &str) -> DateTime<Utc> {
+        DateTime::parse_from_rfc3339(time)
+            .unwrap()
+            .with_timezone(&Utc)
+    }
+
+    #[test]
+    fn test_next_update() {
+        // Thursday
+        let now = time("2021-01-07T10:30:00+00:00");
+        let mut hints = UpdateHints::default();
+        assert_eq!(
+            next_update(now, 300, &hints, None),
+            time("2021-01-07T10:35:00+00:00")
+        );
+        // The longest between the interval and the hints
+        hints.ttl = Some(60 * 60);
+        assert_eq!(
+            next_update(now, 300, &hints, None),
+            time("2021-01-07T11:30:00+00:00")
+        );
+        assert_eq!(
+            next_update(now, 300, &hints, Some(time("2021-01-07T12:00:00+00:00"))),
+            time("2021-01-07T12:00:00+00:00")
+        );
+        hints.update_period = Some(10 * 24 * 60 * 60);
+        assert_eq!(
+            next_update(now, 300, &hints, None),
+            time("2021-01-08T10:30:00+00:00")
+        );
+        // Skipped hours and days
+        hints = UpdateHints {
+            skip_hours: vec![10, 11],
+            ..UpdateHints::default()
+        };
+        assert_eq!(
+            next_update(now, 300, &hints, None),
+            time("2021-01-07T12:00:00+00:00")
+        );
+        hints.skip_days = vec![Weekday::Thu, Weekday::Fri];
+        assert_eq!(
+            next_update(now, 300, &hints, None),
+            time("2021-01-09T00:00:00+00:00")
+        );
+        // Everything skipped
+        hints.skip_hours = (0..24).collect();
+        assert_eq!(
+            next_update(now, 300, &hints, None),
+            time("2021-01-07T10:35:00+00:00")
+        );
+    }
+
+    #[test]
+    fn test_expires() {
+        let now = time("2015-10-21T07:28:00+00:00");
+        let headers = |values: &[(&'static str, &'static str)]| {
+            let mut headers = HeaderMap::new();
+            for (name, value) in values {
+                headers.insert(*name, value.parse().unwrap());
+            }
+            headers
+        };
+        assert_eq!(expires(&headers(&[]), now), None);
+        assert_eq!(
+            expires(&headers(&[("cache-control", "public, max-age=600")]), now),
+            Some(time("2015-10-21T07:38:00+00:00"))
+        );
+        assert_eq!(
+            expires(
+                &headers(&[("expires", "Wed, 21 Oct 2015 08:28:00 GMT")]),
+                now
+            ),
+            Some(time("2015-10-21T08:28:00+00:00"))
+        );
+        assert_eq!(
+            expires(
+                &headers(&[
+                    ("cache-control", "max-age=60"),
+                    ("expires", "Wed, 21 Oct 2015 08:28:00 GMT")
+                ]),
+                now
+            ),
+            Some(time("2015-10-21T07:29:00+00:00"))
+        );
+        assert_eq!(
+            expires(
+                &headers(&[
+                    ("cache-control", "no-cache"),
+                    ("expires", "Wed, 21 Oct 2015 08:28:00 GMT")
+                ]),
+                now
+            ),
+            None
+        );
+    }
+
+    #[test]
+    fn test_backoff() {
+        for failures in 1..10 {
+            let delay = RETRY_BASE * 2u64.pow(failures - 1);
+            let backoff = backoff(failures).num_seconds() as u64;
+            assert!((delay / 2..=delay * 3 / 2).contains(&backoff));
+        }
+        // The delay doesn't grow over the maximum
+        let backoff = backoff(u32::MAX).num_seconds() as u64;
+        assert!((RETRY_MAX / 2..=RETRY_MAX * 3 / 2).contains(&backoff));
+    }
+
+    #[tokio::test]
+    async fn test_update_cache_failed() {
+        let path = std::env::temp_dir().join("feedrs_update_cache_failed.db");
+        if path.exists() {
+            std::fs::remove_file(&path).unwrap();
+        }
+        let pool = Arc::new(database::get_database(&path).await.unwrap());
+        // The closed pool fails like a locked database
+        pool.close().await;
+        let status = Arc::new(RwLock::new(HashMap::new()));
+        let update = SourceUpdate {
+            url: String::from("source"),
+            articles: Vec::new(),
+            feed: None,
+            status: FetchStatus::default(),
+        };
+        update_cache(&pool, &status, update);
+        while !status.read().unwrap().contains_key("source") {
+            sleep(Duration::from_millis(10)).await;
+        }
+        let source_status = &status.read().unwrap()["source"];
+        assert_eq!(source_status.failures, 1);
+        assert_eq!(
+            source_status.error.as_ref().map(|error| error.kind),
+            Some(ErrorKind::Other)
+        );
+    }
+
+    #[tokio::test]
+    async fn test_fetch_failed() {
+        let path = std::env::temp_dir().join("feedrs_fetch_failed.db");
+        if path.exists() {
+            std::fs::remove_file(&path).unwrap();
+        }
+        let pool = database::get_database(&path).await.unwrap();
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}/feed.xml", listener.local_addr().unwrap());
+        tokio::spawn(serve_conditional(listener));
+        let status = RwLock::new(HashMap::new());
+        let source = Source::new(url.clone());
+        // The served content is not a feed
+        for failures in 1..3 {
+            let fetched = fetch_source(&Client::new(), &pool, &source, 300).await;
+            let update = source_update((url.clone(), fetched), &status);
+            assert!(update.articles.is_empty());
+            assert!(update.feed.is_none());
+            let source_status = &status.read().unwrap()[&url];
+            assert_eq!(update.status, *source_status);
+            assert_eq!(source_status.failures, failures);
+            assert_eq!(
+                source_status.error.as_ref().map(|error| error.kind),
+                Some(ErrorKind::Parse)
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn test_refresh() {
+        let path = std::env::temp_dir().join("feedrs_refresh.db");
+        let _ = std::fs::remove_file(&path);
+        let pool = Arc::new(database::get_database(&path).await.unwrap());
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap();
+        tokio::spawn(serve_conditional(listener));
+        let sources: Vec<Arc<Source>> = (0..2)
+            .map(|i| Arc::new(Source::new(format!("http://{}/{}.xml", address, i))))
+            .collect();
+        let (sender, mut receiver) = mpsc::unbounded_channel();
+        let later = Instant::now() + Duration::from_secs(60 * 60);
+        let mut scheduler = Scheduler {
+            client: Client::new(),
+            pool,
+            hosts: HostLimits::default(),
+            update_interval: 300,
+            sender,
+            next_update: sources
+                .iter()
+                .map(|source| (source.url.clone(), later))
+                .collect(),
+            fetching: Arc::default(),
+        };
+        // Nothing is due
+        scheduler.fetch_due(&sources);
+        assert!(scheduler.fetching.read().unwrap().is_empty());
+        let url = sources[0].url.clone();
+        scheduler.refresh(&sources, &Refresh::Source(url.clone()));
+        assert_eq!(
+            *scheduler.fetching.read().unwrap(),
+            vec![url.clone()].into_iter().collect()
+        );
+        assert_eq!(scheduler.next_due(), Some(later));
+        // The source being fetched is not fetched again
+        scheduler.refresh(&sources, &Refresh::All);
+        assert_eq!(scheduler.fetching.read().unwrap().len(), 2);
+        assert_eq!(scheduler.next_due(), None);
+        let mut urls = vec![
+            receiver.recv().await.unwrap().0,
+            receiver.recv().await.unwrap().0,
+        ];
+        urls.sort();
+        assert_eq!(urls, vec![url, sources[1].url.clone()]);
+        // A new source is fetched immediately, only once
+        let mut sources = sources;
+        let source = Arc::new(Source::new(format!("http://{}/2.xml", address)));
+        for _ in 0..2 {
+            scheduler.subscribe(&mut sources, &source);
+        }
+        assert_eq!(sources.len(), 3);
+        scheduler.refresh(&sources, &Refresh::Subscribe(Arc::clone(&source)));
+        assert_eq!(receiver.recv().await.unwrap().0, source.url);
+    }
+
+    #[test]
+    fn test_error_kind_code() {
+        let kinds = [
+            ErrorKind::Dns,
+            ErrorKind::Tls,
+            ErrorKind::Connect,
+            ErrorKind::Timeout,
+            ErrorKind::Http(404),
+            ErrorKind::Parse,
+            ErrorKind::Other,
+        ];
+        for kind in kinds.iter() {
+            assert_eq!(ErrorKind::from_code(&kind.code()), *kind);
+        }
+        assert_eq!(ErrorKind::from_code("http x"), ErrorKind::Other);
+    }
+
+    #[test]
+    fn test_fetch_status() {
+        let mut status = FetchStatus::default();
+        let error = FetchError {
+            kind: ErrorKind::Http(500),
+            message: String::from("Internal Server Error"),
+            retry_after: None,
+        };
+        let time = Utc::now();
+        status.failed(error.clone(), time);
+        status.failed(error.clone(), time);
+        assert_eq!(status.failures, 2);
+        assert_eq!(status.error, Some(error));
+        // Retried after the backoff of two failures
+        let retry = status.next_retry.unwrap() - time;
+        assert!(retry >= chrono::Duration::seconds(RETRY_BASE as i64));
+        assert!(retry <= chrono::Duration::seconds(RETRY_BASE as i64 * 3));
+        status.succeeded(Utc::now(), time);
+        assert_eq!(status.due(), Some(time));
+        assert_eq!(status.failures, 0);
+        assert_eq!(status.error, None);
+        assert_eq!(status.next_retry, None);
+        assert!(status.last_failure.is_some());
+    }
+
+    #[tokio::test]
+    async fn test_request_content_conditional() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}/feed.xml", listener.local_addr().unwrap());
+        tokio::spawn(serve_conditional(listener));
+        let client = Client::new();
+        let source = Source::new(url);
+
+        let validators = match request_content(&client, &source, &Validators::default())
+            .await
+            .unwrap()
+        {
+            Response::Modified(body, _, validators, _) => {
+                assert_eq!(body, "feed");
+                validators
+            }
+            Response::NotModified(_) => panic!("expected the content of the feed"),
+        };
+        assert_eq!(
+            validators,
+            Validators {
+                etag: Some(String::from("\"v1\"")),
+                last_modified: Some(String::from("Wed, 21 Oct 2015 07:28:00 GMT")),
+            }
+        );
+        assert_eq!(
+            request_content(&client, &source, &validators)
+                .await
+                .unwrap(),
+            Response::NotModified(None)
+        );
+    }
 }
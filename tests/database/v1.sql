@@ -0,0 +1,29 @@
+-- Cache of the first version of the database schema
+CREATE TABLE IF NOT EXISTS Articles (
+  id TEXT NOT NULL,
+  source TEXT NOT NULL,
+  title TEXT NOT NULL,
+  sub_title TEXT NOT NULL,
+  content TEXT NOT NULL,
+  date DATETIME,
+  PRIMARY KEY (id, source)
+);
+INSERT INTO Articles (id, source, title, sub_title, content, date)
+VALUES
+  (
+    'id_1',
+    'source',
+    'title_1',
+    'sub_title_1',
+    'content_1',
+    '2016-11-08 08:50:23'
+  ),
+  (
+    'id_2',
+    'source',
+    'title_2',
+    'sub_title_2',
+    'content_2',
+    NULL
+  );
+PRAGMA user_version = 1;